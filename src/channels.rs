@@ -0,0 +1,187 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::parse::{ParseError, Token};
+
+/// Number of MIDI channels.
+pub const CHANNEL_COUNT: u8 = 16;
+
+/// A set of zero-based MIDI channels to send on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Channels {
+    // bit n set means channel n is selected
+    mask: u16
+}
+
+impl Channels {
+    /// All 16 channels.
+    pub fn all() -> Channels {
+        Channels { mask: 0xFFFF }
+    }
+
+    /// No channels at all.
+    pub fn none() -> Channels {
+        Channels { mask: 0 }
+    }
+
+    /// A single zero-based channel.
+    pub fn only(channel: u8) -> Channels {
+        let mut channels = Channels::none();
+        channels.insert(channel);
+        channels
+    }
+
+    /// Add a zero-based channel to the set. Channels above 15 are ignored.
+    pub fn insert(&mut self, channel: u8) {
+        if channel < CHANNEL_COUNT {
+            self.mask |= 1 << channel;
+        }
+    }
+
+    pub fn contains(&self, channel: u8) -> bool {
+        channel < CHANNEL_COUNT && self.mask & (1 << channel) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mask == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.mask.count_ones() as usize
+    }
+
+    /// Iterate over the selected zero-based channels, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u8> {
+        let channels = *self;
+        (0..CHANNEL_COUNT).filter(move |&channel| channels.contains(channel))
+    }
+
+    /// Parse a human 1-based channel selection such as `1-4,10`.
+    ///
+    /// The selection is a comma-separated list of channels and inclusive ranges of channels.
+    /// As with the original single channel argument, 0 is treated as a synonym for channel 1.
+    pub fn parse(spec: &str) -> Result<Channels, ParseError> {
+        Channels::parse_token(Token { offset: 0, text: spec })
+    }
+
+    /// Parse a channel selection which appears at some offset within larger data.
+    pub fn parse_token(spec: Token) -> Result<Channels, ParseError> {
+        let mut channels = Channels::none();
+
+        for item in spec.fields(',') {
+            match item.fields('-').collect::<Vec<_>>()[..] {
+                [channel] => channels.insert(channel_number(channel)?),
+                [first, last] => {
+                    let (first, last) = (channel_number(first)?, channel_number(last)?);
+                    if first > last {
+                        return Err(item.unexpected());
+                    }
+                    (first..=last).for_each(|channel| channels.insert(channel));
+                }
+                _ => return Err(item.unexpected())
+            }
+        }
+
+        Ok(channels)
+    }
+}
+
+// convert from human 1-based channel index, to 0-based indexing
+fn channel_number(token: Token) -> Result<u8, ParseError> {
+    let channel = token.number(u32::from(CHANNEL_COUNT))? as u8;
+    // treat input of 0 as being synonymous with channel 1
+    Ok(channel.saturating_sub(1))
+}
+
+impl Default for Channels {
+    fn default() -> Channels {
+        Channels::all()
+    }
+}
+
+impl FromStr for Channels {
+    type Err = ParseError;
+
+    fn from_str(spec: &str) -> Result<Channels, ParseError> {
+        Channels::parse(spec)
+    }
+}
+
+// formats as a 1-based selection which `Channels::parse` accepts
+impl fmt::Display for Channels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        let mut channel = 0;
+
+        while channel < CHANNEL_COUNT {
+            if !self.contains(channel) {
+                channel += 1;
+                continue;
+            }
+
+            // find the end of this run of selected channels
+            let start = channel;
+            while channel + 1 < CHANNEL_COUNT && self.contains(channel + 1) {
+                channel += 1;
+            }
+
+            if !first {
+                write!(f, ",")?;
+            }
+            first = false;
+
+            if start == channel {
+                write!(f, "{}", start + 1)?;
+            }
+            else {
+                write!(f, "{}-{}", start + 1, channel + 1)?;
+            }
+            channel += 1;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::ParseErrorKind;
+
+    fn channels(spec: &str) -> Vec<u8> {
+        Channels::parse(spec).unwrap().iter().collect()
+    }
+
+    #[test]
+    fn parses_lists_and_ranges() {
+        assert_eq!(channels("1"),       vec![0]);
+        assert_eq!(channels("16"),      vec![15]);
+        assert_eq!(channels("1-4,10"),  vec![0, 1, 2, 3, 9]);
+        assert_eq!(channels("3,1,3"),   vec![0, 2]);
+        assert_eq!(channels("1-16").len(), 16);
+    }
+
+    #[test]
+    fn zero_means_channel_one() {
+        assert_eq!(channels("0"), vec![0]);
+    }
+
+    #[test]
+    fn rejects_bad_selections() {
+        let error = |spec| Channels::parse(spec).unwrap_err();
+
+        assert_eq!(error("17"),
+                   ParseError::new(0, ParseErrorKind::OutOfRange { value: "17".into(), max: 16 }));
+        assert_eq!(error("1-4,x").offset, 4);
+        assert_eq!(error("4-1"),   ParseError::new(0, ParseErrorKind::UnexpectedToken("4-1".into())));
+        assert_eq!(error("1-2-3"), ParseError::new(0, ParseErrorKind::UnexpectedToken("1-2-3".into())));
+        assert_eq!(error("").kind, ParseErrorKind::InvalidNumber("".into()));
+    }
+
+    #[test]
+    fn displays_as_parseable_selection() {
+        assert_eq!(Channels::parse("1-4,10,12-13").unwrap().to_string(), "1-4,10,12-13");
+        assert_eq!(Channels::all().to_string(), "1-16");
+        assert_eq!(Channels::none().to_string(), "");
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+
+use crate::channels::Channels;
+use crate::emitter::Action;
+use crate::error::Error;
+use crate::macros::{Arguments, Macro};
+use crate::names::{self, ControllerNames};
+use crate::parse::Parser;
+use crate::ports::{PortFilter, PortSelector};
+use crate::route::Route;
+
+/// Location of the config file, relative to the user's config directory.
+pub const CONFIG_FILE: &str = "cc-emitter/config.toml";
+
+/// Settings read from the config file.
+///
+/// ```toml
+/// [device.juno]
+/// port = "JUNO-DS"
+/// channel = 1
+///
+/// [macro]
+/// local-off = "122:0"
+///
+/// [alias]
+/// filter = 74
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Named devices, selectable with `-d <name>`.
+    #[serde(default)]
+    pub device: BTreeMap<String, Device>,
+    /// Named macros, run with `cc-emitter run <name>`.
+    #[serde(default, rename = "macro")]
+    pub macros: BTreeMap<String, Macro>,
+    /// Named routes, run with `cc-emitter route <name>`.
+    #[serde(default, rename = "route")]
+    pub routes: BTreeMap<String, Route>,
+    /// Controller names, from the `[alias]` table, usable in data as well as the standard ones.
+    #[serde(default, rename = "alias", deserialize_with = "deserialize_aliases")]
+    pub names:  ControllerNames
+}
+
+/// A named device: which ports it's on and which channels it listens to.
+///
+/// The port options mirror the command line ones, and combine with them in the same way.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Device {
+    /// Name contains this string.
+    pub port:         Option<String>,
+    /// Name matches this regular expression.
+    pub port_regex:   Option<String>,
+    /// Name is exactly this string.
+    pub port_exact:   Option<String>,
+    /// Skip ports whose name contains any of these.
+    pub exclude_port: Vec<String>,
+    /// Channels to send on, as a number or a selection such as `"1-4,10"`.
+    #[serde(deserialize_with = "deserialize_channels")]
+    pub channel:      Option<Channels>,
+    /// A controller map file naming this device's controllers; see `names::read_map`. A relative
+    /// path is relative to the config file.
+    pub map:          Option<PathBuf>,
+    /// A macro to run on each of the device's ports as it's connected by the long-running
+    /// commands, to set up devices when they're plugged in.
+    pub on_connect:   Option<String>
+}
+
+impl Device {
+    /// The ports this device is on.
+    pub fn selector(&self) -> Result<PortSelector, Error> {
+        let mut ports = PortSelector::all();
+
+        if let Some(ref filter) = self.port {
+            ports = ports.include(PortFilter::Contains(filter.clone()));
+        }
+        if let Some(ref pattern) = self.port_regex {
+            ports = ports.include(PortFilter::regex(pattern)?);
+        }
+        if let Some(ref name) = self.port_exact {
+            ports = ports.include(PortFilter::Exact(name.clone()));
+        }
+        for filter in self.exclude_port.iter() {
+            ports = ports.exclude(PortFilter::Contains(filter.clone()));
+        }
+
+        Ok(ports)
+    }
+
+    /// The controller names to use for this device: `names`, plus those in its map file.
+    pub fn names(&self, names: &ControllerNames) -> Result<ControllerNames, Error> {
+        match self.map {
+            Some(ref path) => names::read_map(path, names.clone()),
+            None           => Ok(names.clone())
+        }
+    }
+}
+
+// channels may be written as a bare number or as a selection string
+pub(crate) fn deserialize_channels<'de, D>(deserializer: D) -> Result<Option<Channels>, D::Error>
+    where D: Deserializer<'de>
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Number(u32),
+        Selection(String)
+    }
+
+    let spec = match Setting::deserialize(deserializer)? {
+        Setting::Number(n)         => n.to_string(),
+        Setting::Selection(string) => string
+    };
+
+    Channels::parse(&spec)
+        .map(Some)
+        .map_err(|e| de::Error::custom(format_args!("invalid channel \"{}\": {}", spec, e)))
+}
+
+// aliases are a table of names to controller numbers
+fn deserialize_aliases<'de, D>(deserializer: D) -> Result<ControllerNames, D::Error>
+    where D: Deserializer<'de>
+{
+    let aliases = BTreeMap::<String, u8>::deserialize(deserializer)?;
+    aliases.iter().try_fold(ControllerNames::standard(), |names, (name, &controller)| {
+        names.alias(name, controller)
+            .ok_or_else(|| de::Error::custom(format_args!("invalid alias {} = {}", name, controller)))
+    })
+}
+
+impl Config {
+    /// Parse config file contents.
+    pub fn parse(text: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Read and parse a config file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|error| Error::Io { path: path.to_path_buf(), error })?;
+        let mut config = Config::parse(&text)
+            .map_err(|error| Error::Config { path: path.to_path_buf(), error })?;
+
+        // map files live alongside the config file
+        if let Some(dir) = path.parent() {
+            for device in config.device.values_mut() {
+                device.map = device.map.take().map(|map| dir.join(map));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Read the config file from its default location, if there is one.
+    ///
+    /// A missing file is treated as an empty config.
+    pub fn load_default() -> Result<Config, Error> {
+        match default_path() {
+            Some(path) => match Config::load(&path) {
+                Err(Error::Io { ref error, .. }) if error.kind() == io::ErrorKind::NotFound =>
+                    Ok(Config::default()),
+                result => result
+            },
+            None => Ok(Config::default())
+        }
+    }
+
+    /// Look up a named macro and expand it with `arguments`.
+    pub fn expand_macro(&self, name: &str, arguments: &Arguments, parser: &Parser)
+        -> Result<Vec<Action>, Error>
+    {
+        let definition = self.macros.get(name).ok_or_else(|| Error::UnknownMacro(name.to_string()))?;
+        definition.expand(arguments, parser)
+            .map_err(|error| Error::Macro { name: name.to_string(), error: Box::new(error) })
+    }
+
+    /// Look up a named device.
+    pub fn device(&self, name: &str) -> Result<&Device, Error> {
+        self.device.get(name).ok_or_else(|| Error::UnknownDevice(name.to_string()))
+    }
+
+    /// Look up a named route.
+    pub fn route(&self, name: &str) -> Result<&Route, Error> {
+        self.routes.get(name).ok_or_else(|| Error::UnknownRoute(name.to_string()))
+    }
+}
+
+/// The default config file location: `$XDG_CONFIG_HOME/cc-emitter/config.toml`, falling back to
+/// `~/.config/cc-emitter/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_CONFIG_HOME", Some(".config"))?.join(CONFIG_FILE))
+}
+
+/// The directory named by an XDG base directory variable such as `XDG_DATA_HOME`, or failing that
+/// `home_suffix` within the home directory, if given.
+pub fn xdg_dir(var: &str, home_suffix: Option<&str>) -> Option<PathBuf> {
+    env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(Path::new(&env::var_os("HOME")?).join(home_suffix?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_devices() {
+        let config = Config::parse(r#"
+            [device.juno]
+            port = "JUNO-DS"
+            channel = 1
+            on-connect = "init"
+
+            [device.drums]
+            port-regex = "^TR-8S"
+            exclude-port = ["Control"]
+            channel = "10,11"
+        "#).unwrap();
+
+        let juno = config.device("juno").unwrap();
+        assert_eq!(juno.port.as_deref(), Some("JUNO-DS"));
+        assert_eq!(juno.channel, Some(Channels::only(0)));
+        assert_eq!(juno.on_connect.as_deref(), Some("init"));
+
+        let drums = config.device("drums").unwrap();
+        assert_eq!(drums.channel, Some(Channels::parse("10-11").unwrap()));
+        let selector = drums.selector().unwrap();
+        assert!(selector.matches(0, "TR-8S:TR-8S 24:0"));
+        assert!(!selector.matches(1, "TR-8S:TR-8S Control 24:1"));
+    }
+
+    #[test]
+    fn device_settings_are_optional() {
+        let config = Config::parse("[device.any]").unwrap();
+        let any = config.device("any").unwrap();
+        assert_eq!(any.channel, None);
+        assert!(any.selector().unwrap().matches(0, "Midi Through"));
+    }
+
+    #[test]
+    fn parses_aliases() {
+        let config = Config::parse("[alias]\nfilter = 74\nres = 71").unwrap();
+        assert_eq!(config.names.lookup("filter"), Some(74));
+        assert_eq!(config.names.lookup("volume"), Some(7));
+
+        assert!(Config::parse("[alias]\nfilter = 128").is_err());
+        assert!(Config::parse("[alias]\npc = 1").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_config() {
+        assert!(Config::parse("[device.juno]\nchannel = 17").is_err());
+        assert!(Config::parse("[device.juno]\nprot = \"JUNO\"").is_err());
+        assert!(matches!(Config::default().device("juno"), Err(Error::UnknownDevice(_))));
+        assert!(matches!(Config::default().expand_macro("x", &Arguments::new(), &Parser::new()),
+                         Err(Error::UnknownMacro(_))));
+        assert!(matches!(Config::default().route("keys"), Err(Error::UnknownRoute(_))));
+        assert!(Config::parse("[route.keys]\nto = \"juno\"").is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use midir::{ConnectError, Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection,
+            PortInfoError, SendError};
+
+use crate::error::Error;
+use crate::output::OutputSink;
+use crate::ports::{self, PortSelector};
+use crate::{INPUT_CONNECTION_NAME, INPUT_PORT_NAME, OUTPUT_CONNECTION_NAME, OUTPUT_PORT_NAME};
+
+/// The kind of port `Connections` are made to: outputs, or inputs with a handler for what arrives.
+pub trait Side {
+    type Client;
+    type Connection: Send;
+
+    /// How the ports are described in messages.
+    const NOUN: &'static str;
+
+    /// A fresh MIDI client to list and connect ports with.
+    fn client(&self) -> Result<Self::Client, Error>;
+
+    /// Query the name of every port, in port number order.
+    fn list(client: &Self::Client) -> Vec<(usize, Result<String, PortInfoError>)>;
+
+    /// Connect to a port, which uses up the client unless connecting fails.
+    fn connect(&mut self, client: Self::Client, port: usize, name: &str)
+        -> Result<Self::Connection, ConnectError<Self::Client>>;
+}
+
+/// Output ports, to send messages to.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Outputs;
+
+impl Side for Outputs {
+    type Client     = MidiOutput;
+    type Connection = MidiOutputConnection;
+
+    const NOUN: &'static str = "port";
+
+    fn client(&self) -> Result<MidiOutput, Error> {
+        Ok(ports::make_output()?)
+    }
+
+    fn list(client: &MidiOutput) -> Vec<(usize, Result<String, PortInfoError>)> {
+        ports::list(client)
+    }
+
+    fn connect(&mut self, client: MidiOutput, port: usize, _name: &str)
+        -> Result<MidiOutputConnection, ConnectError<MidiOutput>>
+    {
+        client.connect(port, OUTPUT_CONNECTION_NAME)
+    }
+}
+
+/// Input ports, whose messages are passed to a handler along with the port's name.
+///
+/// The handler is called on a background thread, and receives everything including SysEx and
+/// timing messages.
+#[derive(Clone)]
+pub struct Inputs<F> {
+    handler: F
+}
+
+impl<F> Side for Inputs<F>
+    where F: FnMut(&str, &[u8]) + Send + Clone + 'static
+{
+    type Client     = MidiInput;
+    type Connection = MidiInputConnection<()>;
+
+    const NOUN: &'static str = "input port";
+
+    fn client(&self) -> Result<MidiInput, Error> {
+        let mut input = ports::make_input()?;
+        input.ignore(Ignore::None);
+        Ok(input)
+    }
+
+    fn list(client: &MidiInput) -> Vec<(usize, Result<String, PortInfoError>)> {
+        ports::list_inputs(client)
+    }
+
+    fn connect(&mut self, client: MidiInput, port: usize, name: &str)
+        -> Result<MidiInputConnection<()>, ConnectError<MidiInput>>
+    {
+        let mut handler = self.handler.clone();
+        let port_name   = name.to_string();
+        client.connect(port, INPUT_CONNECTION_NAME,
+                       move |_, message, _| handler(&port_name, message), ())
+    }
+}
+
+// called with each port's name and connection as it's connected
+type OnConnect<C> = Box<dyn FnMut(&str, &mut C) + Send>;
+
+/// Connections to every port matching a selector, kept up to date as devices come and go.
+///
+/// Ports are told apart by name rather than number, since numbers shift when devices are plugged
+/// in or unplugged. Sending to output connections sends to each in turn, and a connection which
+/// fails is dropped until a rescan finds its port again.
+///
+/// Our own ports are never connected to on either side, whatever the selector says, so a catch-all
+/// selector can't make messages feed back into this process.
+pub struct Connections<S: Side = Outputs> {
+    side:       S,
+    selector:   PortSelector,
+    verbose:    bool,
+    scanned:    bool,
+    on_connect: Option<OnConnect<S::Connection>>,
+    ports:      Vec<(String, S::Connection)>
+}
+
+impl Connections {
+    /// Output connections; `rescan` makes them.
+    pub fn new(selector: PortSelector) -> Connections {
+        Connections::on_side(Outputs, selector)
+    }
+}
+
+impl<F> Connections<Inputs<F>>
+    where F: FnMut(&str, &[u8]) + Send + Clone + 'static
+{
+    /// Input connections, calling `handler` with the port's name and the bytes of each message
+    /// received; `rescan` makes them.
+    pub fn inputs(selector: PortSelector, handler: F) -> Connections<Inputs<F>> {
+        Connections::on_side(Inputs { handler }, selector)
+    }
+}
+
+impl<S: Side> Connections<S> {
+    fn on_side(side: S, selector: PortSelector) -> Connections<S> {
+        Connections { side, selector, verbose: false, scanned: false, on_connect: None,
+                      ports: Vec::new() }
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Connections<S> {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Call `on_connect` with each port's name and connection when it's connected, before anything
+    /// else is sent to it, e.g. to initialise freshly attached devices.
+    pub fn on_connect<C>(mut self, on_connect: C) -> Connections<S>
+        where C: FnMut(&str, &mut S::Connection) + Send + 'static
+    {
+        self.on_connect = Some(Box::new(on_connect));
+        self
+    }
+
+    /// Select different ports from the next rescan on.
+    pub fn select(&mut self, selector: PortSelector) {
+        self.selector = selector;
+    }
+
+    pub fn len(&self) -> usize {
+        self.ports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+
+    /// The names of the connected ports.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.ports.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Connect to matching ports which aren't connected yet, and drop connections to ports which
+    /// have gone away or no longer match.
+    ///
+    /// Ports which can't be queried or connected to are reported on stderr and skipped.
+    pub fn rescan(&mut self) -> Result<(), Error> {
+        let client = self.side.client()?;
+        // ports which don't match are only worth mentioning once
+        let report_skipped = self.verbose && !self.scanned;
+        self.scanned = true;
+
+        let mut available = Vec::new();
+        for (port, name) in S::list(&client) {
+            match name {
+                Ok(ref name) if is_own_port(name) => (),
+                Ok(name) if self.selector.matches(port, &name) =>
+                    available.push((port, name)),
+                Ok(name) if report_skipped =>
+                    println!("Skipping {} #{} \"{}\" because it doesn't match {}", S::NOUN, port,
+                             name, self.selector),
+                Ok(_)    => (),
+                Err(e)   => eprintln!("Failed to get {} #{} name: {:?}. Skipping this port.",
+                                      S::NOUN, port, e)
+            }
+        }
+
+        let connected = self.ports.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        let (keep, new) = reconcile(&connected, available);
+
+        let mut keep = keep.into_iter();
+        let verbose  = self.verbose;
+        self.ports.retain(|(name, _)| {
+            let kept = keep.next().unwrap_or(false);
+            if !kept && verbose {
+                println!("Disconnecting from \"{}\"", name);
+            }
+            kept
+        });
+
+        // connecting uses up the client, so a fresh one is made for each port after the first.
+        // The port numbers may have shifted since listing them; a mismatch will be put right by
+        // the next rescan.
+        let mut spare = Some(client);
+        for (port, name) in new {
+            if self.verbose {
+                println!("Connecting to {} #{} \"{}\"", S::NOUN, port, name);
+            }
+
+            let client = match spare.take() {
+                Some(client) => client,
+                None         => self.side.client()?
+            };
+
+            match self.side.connect(client, port, &name) {
+                Ok(mut conn) => {
+                    if let Some(ref mut on_connect) = self.on_connect {
+                        on_connect(&name, &mut conn);
+                    }
+                    self.ports.push((name, conn));
+                }
+                Err(e) => {
+                    eprintln!("Failed to connect to {} #{} \"{}\": {:?}", S::NOUN, port, name, e);
+                    spare = Some(e.into_inner());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// whether a port belongs to this program, judging by its client's name
+fn is_own_port(name: &str) -> bool {
+    name.starts_with(OUTPUT_PORT_NAME) || name.starts_with(INPUT_PORT_NAME)
+}
+
+impl<S: Side> fmt::Debug for Connections<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connections")
+            .field("selector", &self.selector)
+            .field("ports", &self.names().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl OutputSink for Connections {
+    fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        // a port which can't be sent to has probably been unplugged, so stop trying until it's
+        // found again
+        self.ports.retain_mut(|(name, conn)| match conn.send(message) {
+            Ok(())  => true,
+            Err(e)  => {
+                eprintln!("Failed to send to \"{}\", disconnecting: {:?}", name, e);
+                false
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Rescan `connections` every `period` on a background thread, for as long as the program runs.
+pub fn watch<S>(connections: Arc<Mutex<Connections<S>>>, period: Duration)
+    where S: Side + Send + 'static
+{
+    thread::spawn(move || loop {
+        thread::sleep(period);
+
+        let result = connections.lock().unwrap_or_else(PoisonError::into_inner).rescan();
+        if let Err(e) = result {
+            eprintln!("Failed to rescan ports: {}", e);
+        }
+    });
+}
+
+// decide which connections to keep, given the connected port names and the matching ports
+// available now, and which of those ports still need connecting. Several ports may share a
+// name, so each connection claims one port with its name.
+fn reconcile(connected: &[&str], mut available: Vec<(usize, String)>)
+    -> (Vec<bool>, Vec<(usize, String)>)
+{
+    let keep = connected.iter().map(|&name| {
+        match available.iter().position(|(_, candidate)| candidate == name) {
+            Some(i) => {
+                available.remove(i);
+                true
+            }
+            None => false
+        }
+    }).collect();
+
+    (keep, available)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports(names: &[&str]) -> Vec<(usize, String)> {
+        names.iter().enumerate().map(|(i, name)| (i, name.to_string())).collect()
+    }
+
+    #[test]
+    fn keeps_present_ports_and_connects_new_ones() {
+        let (keep, new) = reconcile(&["JUNO 20:0", "TR-8S 24:0"], ports(&["TR-8S 24:0", "MS-20 28:0"]));
+        assert_eq!(keep, vec![false, true]);
+        assert_eq!(new,  vec![(1, "MS-20 28:0".to_string())]);
+    }
+
+    #[test]
+    fn ports_sharing_a_name_are_counted() {
+        let (keep, new) = reconcile(&["Synth", "Synth"], ports(&["Synth"]));
+        assert_eq!(keep, vec![true, false]);
+        assert!(new.is_empty());
+
+        let (keep, new) = reconcile(&["Synth"], ports(&["Synth", "Synth"]));
+        assert_eq!(keep, vec![true]);
+        assert_eq!(new,  vec![(1, "Synth".to_string())]);
+    }
+
+    #[test]
+    fn recognises_our_own_ports() {
+        assert!(is_own_port(&format!("{}:{} connection 128:0", INPUT_PORT_NAME, INPUT_PORT_NAME)));
+        assert!(is_own_port(&format!("{}:{} connection 129:0", OUTPUT_PORT_NAME,
+                                     OUTPUT_PORT_NAME)));
+        assert!(!is_own_port("JUNO-X:JUNO-X MIDI 1 20:0"));
+    }
+}
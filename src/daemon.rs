@@ -0,0 +1,252 @@
+use std::env;
+use std::fs::{self, DirBuilder, Permissions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::config;
+use crate::emitter::Emitter;
+use crate::error::Error;
+use crate::output::OutputSink;
+use crate::parse::Parser;
+use crate::repl::Line;
+
+/// The socket's file name, in the runtime directory.
+pub const SOCKET_FILE: &str = "cc-emitter.sock";
+
+/// The default socket location: `$XDG_RUNTIME_DIR/cc-emitter.sock`, falling back to a private
+/// directory in the temporary directory named after the user.
+pub fn socket_path() -> PathBuf {
+    match config::xdg_dir("XDG_RUNTIME_DIR", None) {
+        Some(dir) => dir.join(SOCKET_FILE),
+        None      => fallback_dir().join(SOCKET_FILE)
+    }
+}
+
+/// The directory for the socket when there's no runtime directory.
+fn fallback_dir() -> PathBuf {
+    let user = env::var("USER").or_else(|_| env::var("LOGNAME")).unwrap_or_default();
+    env::temp_dir().join(format!("cc-emitter-{}", user))
+}
+
+/// Make sure `dir` exists and only its owner can get into it.
+///
+/// The temporary directory is shared, so someone else may have made it first. It's refused unless
+/// it's closed to everyone but its owner, and if that owner isn't us, binding in it will fail.
+fn private_dir(dir: &Path) -> Result<(), Error> {
+    let socket_error = |error| Error::Socket { path: dir.to_path_buf(), error };
+
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+            let metadata = fs::symlink_metadata(dir).map_err(socket_error)?;
+            if metadata.is_dir() && metadata.permissions().mode() & 0o077 == 0 {
+                Ok(())
+            }
+            else {
+                Err(Error::Daemon(format!("{} is open to other users", dir.display())))
+            }
+        }
+        Err(e) => Err(socket_error(e))
+    }
+}
+
+/// Start listening on `path`, replacing the socket of a daemon which is no longer running.
+///
+/// Only our own user can connect to the socket.
+pub fn listen(path: &Path) -> Result<UnixListener, Error> {
+    let socket_error = |error| Error::Socket { path: path.to_path_buf(), error };
+
+    if path.parent() == Some(&fallback_dir()) {
+        private_dir(&fallback_dir())?;
+    }
+
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(Error::Daemon(format!("already running on {}", path.display())));
+        }
+        fs::remove_file(path).map_err(socket_error)?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(socket_error)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600)).map_err(socket_error)?;
+    Ok(listener)
+}
+
+/// Answer clients forever, each on its own thread, sending their messages to `conn`.
+///
+/// Each client starts with the emitter's channels. Problems talking to a client only end that
+/// client's connection, and a client which stays connected doesn't hold up the others.
+pub fn serve<S: OutputSink + Send>(listener: &UnixListener, conn: &Mutex<S>, emitter: &Emitter,
+                                   parser: &Parser) {
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e)     => {
+                    eprintln!("Failed to accept client: {}", e);
+                    continue;
+                }
+            };
+
+            scope.spawn(move || {
+                let result = stream.try_clone().and_then(|reader| {
+                    handle(BufReader::new(reader), stream, &mut &*conn, emitter, parser)
+                });
+
+                if let Err(e) = result {
+                    eprintln!("Failed to talk to client: {}", e);
+                }
+            });
+        }
+    });
+}
+
+/// Answer one client's lines.
+///
+/// Clients write lines in the REPL syntax: data, or `.channel` to change channels for the rest of
+/// the connection. Each line is answered with `ok`, or `error ` followed by a description.
+pub fn handle<R, W, S>(reader: R, mut writer: W, conn: &mut S, emitter: &Emitter, parser: &Parser)
+    -> io::Result<()>
+    where R: BufRead, W: Write, S: OutputSink + ?Sized
+{
+    let mut session = emitter.clone();
+
+    for line in reader.lines() {
+        let result = Line::parse(&line?, parser).and_then(|line| {
+            match line {
+                Line::Empty             => (),
+                Line::Data(program)     => session.emit_to(conn, &program),
+                Line::Channel(channels) => session.channels = channels,
+                _ => return Err(Error::Daemon("only data and .channel can be sent".to_string()))
+            }
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => writeln!(writer, "ok")?,
+            Err(e) => writeln!(writer, "error {}", e)?
+        }
+    }
+
+    Ok(())
+}
+
+/// Send lines to a running daemon.
+///
+/// Returns `Ok(false)` if no daemon is listening on `path`, so the caller can send the messages
+/// itself, and the first error the daemon reports, if any.
+pub fn forward<S: AsRef<str>>(path: &Path, lines: &[S]) -> Result<bool, Error> {
+    let socket_error = |error| Error::Socket { path: path.to_path_buf(), error };
+
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(_)     => return Ok(false)
+    };
+
+    for line in lines.iter() {
+        writeln!(stream, "{}", line.as_ref()).map_err(socket_error)?;
+    }
+    stream.shutdown(std::net::Shutdown::Write).map_err(socket_error)?;
+
+    for reply in BufReader::new(stream).lines() {
+        let reply = reply.map_err(socket_error)?;
+        if let Some(message) = reply.strip_prefix("error ") {
+            return Err(Error::Daemon(message.to_string()));
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channels::Channels;
+    use crate::output::Recorder;
+    use crate::ports::PortSelector;
+
+    fn replies(input: &str, recorder: &mut Recorder) -> String {
+        let mut output = Vec::new();
+        handle(input.as_bytes(), &mut output, recorder,
+               &Emitter::new(PortSelector::all(), Channels::only(0)), &Parser::new()).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn sends_data_and_changes_channels() {
+        let mut recorder = Recorder::new();
+        assert_eq!(replies("74:100\n.channel 2\n\n74:1\n", &mut recorder), "ok\nok\nok\nok\n");
+        assert_eq!(recorder.sent, vec![vec![0xB0, 74, 100], vec![0xB1, 74, 1]]);
+    }
+
+    #[test]
+    fn reports_errors_per_line() {
+        let mut recorder = Recorder::new();
+        let output = replies("74:200\n.port JUNO\n1:1\n", &mut recorder);
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert!(lines[0].starts_with("error Invalid data"));
+        assert!(lines[1].starts_with("error"));
+        assert_eq!(lines[2], "ok");
+        assert_eq!(recorder.sent, vec![vec![0xB0, 1, 1]]);
+    }
+
+    #[test]
+    fn forwards_to_a_listening_daemon() {
+        let path = env::temp_dir().join(format!("cc-emitter-test-{}.sock", std::process::id()));
+        assert!(!forward(&path, &["1:1"]).unwrap());
+
+        let listener = listen(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut recorder = Recorder::new();
+            handle(BufReader::new(stream.try_clone().unwrap()), stream, &mut recorder,
+                   &Emitter::new(PortSelector::all(), Channels::only(0)), &Parser::new()).unwrap();
+            recorder.sent
+        });
+
+        assert!(forward(&path, &["1:1", "2:2"]).unwrap());
+        assert_eq!(server.join().unwrap(), vec![vec![0xB0, 1, 1], vec![0xB0, 2, 2]]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_socket_private() {
+        let dir = env::temp_dir().join(format!("cc-emitter-test-{}", std::process::id()));
+        private_dir(&dir).unwrap();
+        private_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+
+        let path = dir.join(SOCKET_FILE);
+        let listener = listen(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        drop(listener);
+
+        fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        assert!(private_dir(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn serves_clients_alongside_an_idle_one() {
+        let path = env::temp_dir().join(format!("cc-emitter-test-idle-{}.sock",
+                                                std::process::id()));
+        let listener = listen(&path).unwrap();
+        let recorder = std::sync::Arc::new(Mutex::new(Recorder::new()));
+
+        let sink = recorder.clone();
+        thread::spawn(move || {
+            serve(&listener, &*sink, &Emitter::new(PortSelector::all(), Channels::only(0)),
+                  &Parser::new());
+        });
+
+        let _idle = UnixStream::connect(&path).unwrap();
+        assert!(forward(&path, &["1:1"]).unwrap());
+        assert_eq!(recorder.lock().unwrap().sent, vec![vec![0xB0, 1, 1]]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::channels::Channels;
+use crate::connections::Connections;
+use crate::error::Error;
+use crate::lfo::Lfo;
+use crate::message::{ControlChange, Message};
+use crate::names::ControllerNames;
+use crate::output::OutputSink;
+use crate::parse::{Program, MAX_TIME};
+use crate::ports::PortSelector;
+use crate::sysex::SysEx;
+
+/// One part of a sequence sent to each port, such as an expanded macro.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Channel messages, sent on the selected channels.
+    Program(Program),
+    /// SysEx messages, each sent once.
+    SysEx(Vec<SysEx>)
+}
+
+/// Sends a `Program` to every matching port, on every selected channel.
+#[derive(Debug, Clone, Default)]
+pub struct Emitter {
+    pub ports:    PortSelector,
+    pub channels: Channels,
+    pub verbose:  bool,
+    /// Names to show for controllers in verbose output.
+    pub names:    ControllerNames,
+    /// The least time to leave between consecutive messages, for devices which drop messages
+    /// that arrive too quickly.
+    pub interval: Duration
+}
+
+impl Emitter {
+    pub fn new(ports: PortSelector, channels: Channels) -> Emitter {
+        Emitter {
+            ports, channels,
+            verbose:  false,
+            names:    ControllerNames::standard(),
+            interval: Duration::from_secs(0)
+        }
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Emitter {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn names(mut self, names: ControllerNames) -> Emitter {
+        self.names = names;
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Emitter {
+        self.interval = interval;
+        self
+    }
+
+    /// Emit the program to a given output.
+    ///
+    /// Each message is sent on every selected channel (or the channels it overrides the
+    /// selection with) before moving on to the next one. Messages due later in the program, such
+    /// as ramp steps and those after a `wait`, are held back until their time comes, and every
+    /// message is kept at least `interval` after the one before.
+    pub fn emit_to<S: OutputSink + ?Sized>(&self, conn: &mut S, program: &Program) {
+        let mut pacer = Pacer::new(self.interval);
+
+        for event in program.events.iter() {
+            for channel in event.channels.unwrap_or(self.channels).iter() {
+                pacer.wait(event.at);
+                let message = event.message.on_channel(channel);
+
+                if self.verbose {
+                    println!("Sending {} on ch#{}", message.describe(&self.names), channel+1);
+                }
+
+                conn.send(&message.to_bytes())
+                    .unwrap_or_else(|e| eprintln!("Failed to send {} on ch#{}: {:?}",
+                                                  message, channel+1, e));
+            }
+        }
+    }
+
+    /// Send SysEx messages to a given output, waiting `delay` (or `interval`, if longer) between
+    /// consecutive messages.
+    ///
+    /// SysEx isn't addressed to a channel, so each message is sent exactly once.
+    pub fn emit_sysex_to<S: OutputSink + ?Sized>(&self, conn: &mut S, messages: &[SysEx],
+                                                 delay: Duration) {
+        let mut pacer = Pacer::new(delay.max(self.interval));
+
+        for message in messages.iter() {
+            pacer.wait(Duration::from_secs(0));
+
+            if self.verbose {
+                println!("Sending SysEx {}", message);
+            }
+
+            conn.send(message.as_bytes())
+                .unwrap_or_else(|e| eprintln!("Failed to send SysEx {}: {:?}", message, e));
+        }
+    }
+
+    /// Send a sequence of programs and SysEx messages to a given output, in order.
+    pub fn emit_actions_to<S: OutputSink + ?Sized>(&self, conn: &mut S, actions: &[Action]) {
+        for (i, action) in actions.iter().enumerate() {
+            // each action paces its own messages, so keep the gap between actions too
+            if i > 0 {
+                thread::sleep(self.interval);
+            }
+
+            match *action {
+                Action::Program(ref program) => self.emit_to(conn, program),
+                Action::SysEx(ref messages)  =>
+                    self.emit_sysex_to(conn, messages, Duration::from_secs(0))
+            }
+        }
+    }
+
+    /// Modulate a controller with an LFO on a given output until `stop` is set, updating it up
+    /// to `rate` times per second.
+    ///
+    /// A value is only sent when it changes. Once stopped, the controller is set to `rest`, if
+    /// given, so it isn't left wherever the LFO happened to be.
+    pub fn emit_lfo_to<S: OutputSink + ?Sized>(&self, conn: &mut S, lfo: &mut Lfo, rate: u32,
+                                               rest: Option<u8>, stop: &AtomicBool) {
+        let tick      = Duration::from_secs(1) / rate.max(1);
+        let mut pacer = Pacer::new(tick.max(self.interval));
+        let mut last  = None;
+
+        while !stop.load(Ordering::SeqCst) {
+            let value = lfo.value_at(pacer.start.elapsed());
+            if last != Some(value) {
+                self.emit_control(conn, lfo.controller, value);
+                last = Some(value);
+            }
+
+            pacer.wait(Duration::from_secs(0));
+        }
+
+        if let Some(value) = rest {
+            self.emit_control(conn, lfo.controller, value);
+        }
+    }
+
+    // send a single Control Change on every selected channel
+    fn emit_control<S: OutputSink + ?Sized>(&self, conn: &mut S, controller: u8, value: u8) {
+        let message = Message::ControlChange(ControlChange::new(0, controller, value));
+        for channel in self.channels.iter() {
+            let message = message.on_channel(channel);
+
+            if self.verbose {
+                println!("Sending {} on ch#{}", message.describe(&self.names), channel+1);
+            }
+
+            conn.send(&message.to_bytes())
+                .unwrap_or_else(|e| eprintln!("Failed to send {} on ch#{}: {:?}",
+                                              message, channel+1, e));
+        }
+    }
+
+    /// Connect to each available port matching the selector and emit the program to it.
+    ///
+    /// Every port is connected before sending starts, so timed messages reach all of them
+    /// together. Failures on individual ports are reported on stderr and skipped.
+    pub fn run(&self, program: &Program) -> Result<(), Error> {
+        self.emit_to(&mut self.connections()?, program);
+        Ok(())
+    }
+
+    /// Connect to each available port matching the selector and send SysEx messages to it.
+    pub fn run_sysex(&self, messages: &[SysEx], delay: Duration) -> Result<(), Error> {
+        self.emit_sysex_to(&mut self.connections()?, messages, delay);
+        Ok(())
+    }
+
+    /// Connect to each available port matching the selector and send a sequence of actions to it.
+    pub fn run_actions(&self, actions: &[Action]) -> Result<(), Error> {
+        self.emit_actions_to(&mut self.connections()?, actions);
+        Ok(())
+    }
+
+    /// Connect to each available port matching the selector and run an LFO on it until `stop` is
+    /// set.
+    pub fn run_lfo(&self, lfo: &mut Lfo, rate: u32, rest: Option<u8>, stop: &AtomicBool)
+        -> Result<(), Error>
+    {
+        self.emit_lfo_to(&mut self.connections()?, lfo, rate, rest, stop);
+        Ok(())
+    }
+
+    // connect to every port matching the selector
+    fn connections(&self) -> Result<Connections, Error> {
+        let mut connections = Connections::new(self.ports.clone()).verbose(self.verbose);
+        connections.rescan()?;
+        Ok(connections)
+    }
+}
+
+// holds messages back until they're due, keeping consecutive messages `interval` apart
+struct Pacer {
+    start:    Instant,
+    interval: Duration,
+    last:     Option<Instant>
+}
+
+impl Pacer {
+    fn new(interval: Duration) -> Pacer {
+        Pacer { start: Instant::now(), interval: interval.min(MAX_TIME), last: None }
+    }
+
+    // wait until `at` after the start, and at least `interval` after the previous message. Times
+    // are capped as the parser caps them, so programs built by hand can't overflow the clock.
+    fn wait(&mut self, at: Duration) {
+        let mut due = self.start + at.min(MAX_TIME);
+        if let Some(last) = self.last {
+            due = due.max(last + self.interval);
+        }
+
+        // the monotonic clock keeps the schedule steady however long sending takes
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        self.last = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Recorder;
+
+    #[test]
+    fn emits_program_on_single_channel() {
+        let mut recorder = Recorder::new();
+        Emitter::new(PortSelector::all(), Channels::only(2))
+            .emit_to(&mut recorder, &Program::parse("122:0").unwrap());
+        assert_eq!(recorder.sent, vec![vec![0xB2, 122, 0]]);
+    }
+
+    #[test]
+    fn sysex_is_sent_once_regardless_of_channels() {
+        let mut recorder = Recorder::new();
+        let messages = crate::sysex::parse_hex("F0 41 10 F7 F0 7E F7").unwrap();
+        Emitter::new(PortSelector::all(), Channels::all())
+            .emit_sysex_to(&mut recorder, &messages, Duration::from_secs(0));
+        assert_eq!(recorder.sent, vec![vec![0xF0, 0x41, 0x10, 0xF7], vec![0xF0, 0x7E, 0xF7]]);
+    }
+
+    #[test]
+    fn emits_each_message_on_every_channel_in_turn() {
+        let mut recorder = Recorder::new();
+        Emitter::new(PortSelector::all(), Channels::all())
+            .emit_to(&mut recorder, &Program::parse("70:104 74:124").unwrap());
+
+        let on_all = |cc, value| (0u8..16).map(move |ch| vec![0xB0 | ch, cc, value]);
+        let expected: Vec<Vec<u8>> = on_all(70, 104).chain(on_all(74, 124)).collect();
+        assert_eq!(recorder.sent, expected);
+    }
+
+    #[test]
+    fn channel_prefix_replaces_selection() {
+        let mut recorder = Recorder::new();
+        Emitter::new(PortSelector::all(), Channels::parse("1-2").unwrap())
+            .emit_to(&mut recorder, &Program::parse("1:1 ch10/2:2").unwrap());
+        assert_eq!(recorder.sent, vec![vec![0xB0, 1, 1], vec![0xB1, 1, 1], vec![0xB9, 2, 2]]);
+    }
+
+    #[test]
+    fn waits_for_timed_events() {
+        let mut recorder = Recorder::new();
+        let program = Program::parse("74:0..2@40ms").unwrap();
+        let start   = Instant::now();
+        Emitter::new(PortSelector::all(), Channels::only(0)).emit_to(&mut recorder, &program);
+
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(recorder.sent, vec![vec![0xB0, 74, 0], vec![0xB0, 74, 1], vec![0xB0, 74, 2]]);
+    }
+
+    #[test]
+    fn keeps_messages_an_interval_apart() {
+        let mut recorder = Recorder::new();
+        let program = Program::parse("pc:1 wait:20ms 1:1").unwrap();
+        let start   = Instant::now();
+        Emitter::new(PortSelector::all(), Channels::parse("1-2").unwrap())
+            .interval(Duration::from_millis(10))
+            .emit_to(&mut recorder, &program);
+
+        // pc on two channels, 10ms apart, then the CC 20ms after the start and 10ms apart again
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(recorder.sent.len(), 4);
+    }
+
+    #[test]
+    fn lfo_runs_until_stopped_then_rests() {
+        let mut recorder = Recorder::new();
+        let mut lfo  = Lfo::new(74, crate::lfo::Waveform::Square, 10.0, 63.0, 64.0);
+        let stop     = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(120));
+                stop.store(true, Ordering::SeqCst);
+            });
+            Emitter::new(PortSelector::all(), Channels::only(0))
+                .emit_lfo_to(&mut recorder, &mut lfo, 100, Some(0), &stop);
+        });
+
+        // the square wave flips every 50ms, and only changes are sent
+        assert!(recorder.sent.len() >= 3);
+        assert_eq!(recorder.sent[0], vec![0xB0, 74, 127]);
+        assert_eq!(recorder.sent[1], vec![0xB0, 74, 1]);
+        assert_eq!(recorder.sent.last(), Some(&vec![0xB0, 74, 0]));
+    }
+
+    #[test]
+    fn actions_are_sent_in_order() {
+        let mut recorder = Recorder::new();
+        let actions = vec![
+            Action::Program(Program::parse("pc:5").unwrap()),
+            Action::SysEx(crate::sysex::parse_hex("F0 41 F7").unwrap()),
+            Action::Program(Program::parse("74:100").unwrap())
+        ];
+        Emitter::new(PortSelector::all(), Channels::only(0))
+            .emit_actions_to(&mut recorder, &actions);
+        assert_eq!(recorder.sent, vec![vec![0xC0, 5], vec![0xF0, 0x41, 0xF7], vec![0xB0, 74, 100]]);
+    }
+}
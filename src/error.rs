@@ -0,0 +1,207 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use midir::InitError;
+
+use crate::parse::ParseError;
+use crate::sysex::SysExError;
+
+/// Process exit codes, one per class of error. Values follow the BSD `sysexits.h` convention.
+pub mod exit {
+    /// A command line argument was invalid.
+    pub const USAGE: i32 = 64;
+    /// The data given to send was malformed.
+    pub const DATA: i32 = 65;
+    /// An input file couldn't be read.
+    pub const NO_INPUT: i32 = 66;
+    /// MIDI support (or a MIDI port) was unavailable.
+    pub const UNAVAILABLE: i32 = 69;
+    /// The operating system refused a request, such as handling Ctrl-C.
+    pub const OS_ERROR: i32 = 71;
+    /// The config file was invalid.
+    pub const CONFIG: i32 = 78;
+}
+
+/// Any error the library can report.
+#[derive(Debug)]
+pub enum Error {
+    /// A command line argument (named by `name`) could not be parsed.
+    Argument { name: &'static str, value: String, error: ParseError },
+    /// A regular expression argument could not be compiled.
+    Regex(regex::Error),
+    /// The data to send could not be parsed.
+    Parse(ParseError),
+    /// SysEx data was not correctly framed.
+    SysEx(SysExError),
+    /// A file could not be read.
+    Io { path: PathBuf, error: io::Error },
+    /// The config file could not be parsed.
+    Config { path: PathBuf, error: toml::de::Error },
+    /// A controller map file was invalid at the given line (0 if unknown).
+    Map { path: PathBuf, line: usize, reason: String },
+    /// A device name was not defined in the config file.
+    UnknownDevice(String),
+    /// A macro name was not defined in the config file.
+    UnknownMacro(String),
+    /// A route name was not defined in the config file.
+    UnknownRoute(String),
+    /// A rule of the named route was invalid.
+    Route { name: String, reason: String },
+    /// A macro argument was neither `name=value` nor a single bare value.
+    InvalidArgument(String),
+    /// A macro placeholder had no argument to fill it.
+    MissingArgument(String),
+    /// A macro argument wasn't used by any placeholder.
+    UnusedArgument(String),
+    /// A `${` in a macro had no closing `}`.
+    UnterminatedPlaceholder(String),
+    /// A REPL line started with `.` but wasn't a known command.
+    UnknownCommand(String),
+    /// An error occurred while expanding the named macro.
+    Macro { name: String, error: Box<Error> },
+    /// The MIDI backend could not be initialised.
+    Init(InitError),
+    /// The daemon's socket could not be used.
+    Socket { path: PathBuf, error: io::Error },
+    /// The daemon couldn't be started, or reported a problem with a request.
+    Daemon(String),
+    /// The Ctrl-C handler could not be installed.
+    Signal(ctrlc::Error)
+}
+
+impl Error {
+    /// The process exit code for this class of error.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Argument { .. }            => exit::USAGE,
+            Error::Regex(_)                   => exit::USAGE,
+            Error::Parse(_)                   => exit::DATA,
+            Error::SysEx(_)                   => exit::DATA,
+            Error::Io { .. }                  => exit::NO_INPUT,
+            Error::Config { .. }              => exit::CONFIG,
+            Error::Map { .. }                 => exit::CONFIG,
+            Error::UnknownDevice(_)           => exit::USAGE,
+            Error::UnknownMacro(_)            => exit::USAGE,
+            Error::UnknownRoute(_)            => exit::USAGE,
+            Error::Route { .. }               => exit::CONFIG,
+            Error::InvalidArgument(_)         => exit::USAGE,
+            Error::MissingArgument(_)         => exit::USAGE,
+            Error::UnusedArgument(_)          => exit::USAGE,
+            Error::UnterminatedPlaceholder(_) => exit::CONFIG,
+            Error::UnknownCommand(_)          => exit::USAGE,
+            Error::Macro { ref error, .. }    => error.exit_code(),
+            Error::Init(_)                    => exit::UNAVAILABLE,
+            Error::Socket { .. }              => exit::UNAVAILABLE,
+            Error::Daemon(_)                  => exit::UNAVAILABLE,
+            Error::Signal(_)                  => exit::OS_ERROR
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Argument { name, ref error, .. } =>
+                write!(f, "Invalid {}: {}", name, error),
+            Error::Regex(ref e) => write!(f, "Invalid regular expression: {}", e),
+            Error::Parse(ref e) => write!(f, "Invalid data: {}", e),
+            Error::SysEx(ref e) => write!(f, "Invalid SysEx: {}", e),
+            Error::Io { ref path, ref error } =>
+                write!(f, "Failed to read {}: {}", path.display(), error),
+            Error::Config { ref path, ref error } =>
+                write!(f, "Invalid config file {}: {}", path.display(), error),
+            Error::Map { ref path, line: 0, ref reason } =>
+                write!(f, "Invalid controller map {}: {}", path.display(), reason),
+            Error::Map { ref path, line, ref reason } =>
+                write!(f, "Invalid controller map {} line {}: {}", path.display(), line, reason),
+            Error::UnknownDevice(ref name) =>
+                write!(f, "Unknown device \"{}\"; define it as [device.{}] in the config file",
+                       name, name),
+            Error::UnknownMacro(ref name) =>
+                write!(f, "Unknown macro \"{}\"; define it in the [macro] table of the config file",
+                       name),
+            Error::UnknownRoute(ref name) =>
+                write!(f, "Unknown route \"{}\"; define it as [route.{}] in the config file",
+                       name, name),
+            Error::Route { ref name, ref reason } =>
+                write!(f, "Invalid route \"{}\": {}", name, reason),
+            Error::InvalidArgument(ref arg) =>
+                write!(f, "Invalid macro argument \"{}\"; expected name=value", arg),
+            Error::MissingArgument(ref name) =>
+                write!(f, "No argument given for ${{{}}}", name),
+            Error::UnusedArgument(ref name) =>
+                write!(f, "Argument {} isn't used by the macro", name),
+            Error::UnterminatedPlaceholder(ref text) =>
+                write!(f, "Unterminated placeholder in \"{}\"", text),
+            Error::UnknownCommand(ref command) =>
+                write!(f, "Unknown command {}; type .help for a list", command),
+            Error::Macro { ref name, ref error } =>
+                write!(f, "In macro \"{}\": {}", name, error),
+            Error::Init(ref e)  => write!(f, "Failed to open MIDI output: {}", e),
+            Error::Socket { ref path, ref error } =>
+                write!(f, "Failed to use socket {}: {}", path.display(), error),
+            Error::Daemon(ref message) => write!(f, "Daemon: {}", message),
+            Error::Signal(ref e) => write!(f, "Failed to handle Ctrl-C: {}", e)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Argument { ref error, .. } => Some(error),
+            Error::Regex(ref e)               => Some(e),
+            Error::Parse(ref e)               => Some(e),
+            Error::SysEx(ref e)               => Some(e),
+            Error::Io { ref error, .. }       => Some(error),
+            Error::Config { ref error, .. }   => Some(error),
+            Error::Macro { ref error, .. }    => Some(error.as_ref()),
+            Error::Map { .. }
+            | Error::UnknownDevice(_)
+            | Error::UnknownMacro(_)
+            | Error::UnknownRoute(_)
+            | Error::Route { .. }
+            | Error::InvalidArgument(_)
+            | Error::MissingArgument(_)
+            | Error::UnusedArgument(_)
+            | Error::UnterminatedPlaceholder(_)
+            | Error::UnknownCommand(_)
+            | Error::Daemon(_)                  => None,
+            Error::Init(ref e)                => Some(e),
+            Error::Socket { ref error, .. }   => Some(error),
+            Error::Signal(ref e)              => Some(e)
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::Parse(e)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Error {
+        Error::Regex(e)
+    }
+}
+
+impl From<SysExError> for Error {
+    fn from(e: SysExError) -> Error {
+        Error::SysEx(e)
+    }
+}
+
+impl From<InitError> for Error {
+    fn from(e: InitError) -> Error {
+        Error::Init(e)
+    }
+}
+
+impl From<ctrlc::Error> for Error {
+    fn from(e: ctrlc::Error) -> Error {
+        Error::Signal(e)
+    }
+}
@@ -0,0 +1,154 @@
+use std::f64::consts::PI;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::parse::DATA_MAX;
+
+/// The shape of an LFO's cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    Square,
+    /// A new random level each cycle (sample and hold).
+    Random
+}
+
+impl Waveform {
+    /// Look up a waveform by name: `sine`, `triangle`, `square` or `random`.
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "sine"              => Some(Waveform::Sine),
+            "triangle" | "tri"  => Some(Waveform::Triangle),
+            "square"            => Some(Waveform::Square),
+            "random" | "sh"     => Some(Waveform::Random),
+            _                   => None
+        }
+    }
+}
+
+/// A low frequency oscillator driving a controller.
+///
+/// The controller swings `depth` either side of `offset`, clamped to 0-127.
+#[derive(Debug, Clone)]
+pub struct Lfo {
+    pub controller: u8,
+    pub waveform:   Waveform,
+    /// Cycles per second.
+    pub frequency:  f64,
+    pub depth:      f64,
+    pub offset:     f64,
+    // the random waveform's generator, and the cycle and level it's holding
+    random:         u64,
+    held:           Option<(u64, f64)>
+}
+
+impl Lfo {
+    pub fn new(controller: u8, waveform: Waveform, frequency: f64, depth: f64, offset: f64) -> Lfo {
+        // seed from the clock; xorshift needs a non-zero state
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        Lfo { controller, waveform, frequency, depth, offset, random: seed | 1, held: None }
+    }
+
+    /// The controller value `elapsed` after the LFO started.
+    pub fn value_at(&mut self, elapsed: Duration) -> u8 {
+        let position = elapsed.as_secs_f64() * self.frequency;
+        let phase    = position.fract();
+
+        // each waveform swings between -1 and 1
+        let level = match self.waveform {
+            Waveform::Sine     => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Square   => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Random   => self.random_level(position as u64)
+        };
+
+        (self.offset + self.depth * level).round().clamp(0.0, f64::from(DATA_MAX)) as u8
+    }
+
+    // the random level for a cycle, picking a new one when the cycle changes
+    fn random_level(&mut self, cycle: u64) -> f64 {
+        match self.held {
+            Some((held_cycle, level)) if held_cycle == cycle => level,
+            _ => {
+                // xorshift64
+                self.random ^= self.random << 13;
+                self.random ^= self.random >> 7;
+                self.random ^= self.random << 17;
+
+                let level = (self.random >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0;
+                self.held = Some((cycle, level));
+                level
+            }
+        }
+    }
+}
+
+/// Read a frequency in cycles per second, which must be finite and above zero.
+pub fn parse_frequency(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(frequency) if frequency.is_finite() && frequency > 0.0 => Ok(frequency),
+        _ => Err(format!("{} is not a frequency above 0", text))
+    }
+}
+
+/// Read a depth or offset, which must be a finite number.
+pub fn parse_level(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(level) if level.is_finite() => Ok(level),
+        _ => Err(format!("{} is not a finite number", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(waveform: Waveform, millis: u64) -> u8 {
+        Lfo::new(1, waveform, 1.0, 63.0, 64.0).value_at(Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn waveforms_swing_around_the_offset() {
+        assert_eq!(value(Waveform::Sine, 0),    64);
+        assert_eq!(value(Waveform::Sine, 250),  127);
+        assert_eq!(value(Waveform::Sine, 750),  1);
+
+        assert_eq!(value(Waveform::Triangle, 0),   1);
+        assert_eq!(value(Waveform::Triangle, 500), 127);
+
+        assert_eq!(value(Waveform::Square, 100), 127);
+        assert_eq!(value(Waveform::Square, 600), 1);
+    }
+
+    #[test]
+    fn values_are_clamped() {
+        let mut lfo = Lfo::new(1, Waveform::Square, 1.0, 100.0, 100.0);
+        assert_eq!(lfo.value_at(Duration::from_millis(0)),   127);
+        assert_eq!(lfo.value_at(Duration::from_millis(500)), 0);
+    }
+
+    #[test]
+    fn random_holds_for_a_cycle() {
+        let mut lfo = Lfo::new(1, Waveform::Random, 2.0, 63.0, 64.0);
+        let first   = lfo.value_at(Duration::from_millis(0));
+        assert_eq!(lfo.value_at(Duration::from_millis(400)), first);
+
+        let values: Vec<u8> = (0..20).map(|cycle| lfo.value_at(Duration::from_millis(cycle * 500))).collect();
+        assert!(values.iter().all(|&value| (1..=127).contains(&value)));
+        assert!(values.iter().any(|&value| value != first));
+    }
+
+    #[test]
+    fn rejects_unusable_settings() {
+        assert_eq!(parse_frequency("0.5"), Ok(0.5));
+        assert!(parse_frequency("0").is_err());
+        assert!(parse_frequency("-1").is_err());
+        assert!(parse_frequency("nan").is_err());
+        assert!(parse_frequency("inf").is_err());
+
+        assert_eq!(parse_level("-10"), Ok(-10.0));
+        assert!(parse_level("NaN").is_err());
+        assert!(parse_level("-inf").is_err());
+    }
+}
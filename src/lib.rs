@@ -0,0 +1,63 @@
+//! Emit MIDI Control Change (and other channel voice) messages to MIDI output ports.
+//!
+//! Parse a `Program` from the data syntax (see `Parser`), then hand it to an `Emitter` together with a
+//! `PortSelector` and the `Channels` to send on:
+//!
+//! ```no_run
+//! use cc_emitter::{Channels, Emitter, PortSelector, Program};
+//!
+//! # fn main() -> Result<(), cc_emitter::Error> {
+//! let program = Program::parse("122:0")?;
+//! Emitter::new(PortSelector::name_contains("JUNO"), Channels::all())
+//!     .run(&program)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `Emitter::emit_to` accepts any `OutputSink`; a `Recorder` captures the raw bytes instead of
+//! sending them, which is handy for testing without a MIDI device.
+
+pub mod channels;
+pub mod config;
+pub mod connections;
+#[cfg(unix)]
+pub mod daemon;
+pub mod emitter;
+pub mod error;
+pub mod lfo;
+pub mod macros;
+pub mod message;
+pub mod monitor;
+pub mod names;
+pub mod output;
+pub mod panic;
+pub mod parse;
+pub mod ports;
+pub mod ramp;
+pub mod repl;
+pub mod route;
+pub mod sysex;
+
+pub use crate::channels::Channels;
+pub use crate::config::{Config, Device};
+pub use crate::connections::Connections;
+pub use crate::emitter::{Action, Emitter};
+pub use crate::error::Error;
+pub use crate::lfo::{Lfo, Waveform};
+pub use crate::macros::Macro;
+pub use crate::message::{ControlChange, Message};
+pub use crate::names::ControllerNames;
+pub use crate::output::{OutputSink, Recorder};
+pub use crate::parse::{Event, ParseError, Parser, Program};
+pub use crate::ports::{Direction, PortFilter, PortInfo, PortSelector};
+pub use crate::route::{Route, Router};
+pub use crate::sysex::SysEx;
+
+// Display name for output port
+pub const OUTPUT_PORT_NAME: &str = "@selenologist CC emitter";
+// Display name for input port
+pub const INPUT_PORT_NAME: &str = "@selenologist CC emitter input";
+// Name to be displayed on connections
+pub const OUTPUT_CONNECTION_NAME: &str = "@selenologist CC emitter connection";
+// Name to be displayed on input connections
+pub const INPUT_CONNECTION_NAME: &str = "@selenologist CC emitter input connection";
@@ -0,0 +1,185 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+
+use crate::emitter::Action;
+use crate::error::Error;
+use crate::parse::Parser;
+use crate::sysex;
+
+/// Values for the `${name}` placeholders in a macro.
+pub type Arguments = BTreeMap<String, String>;
+
+/// A named sequence of messages from the config file.
+///
+/// A macro is either a single string of data, or an array mixing data strings and SysEx:
+///
+/// ```toml
+/// [macro]
+/// local-off = "122:0"
+/// init-patch = ["pc:5", { sysex = "F0 41 10 42 12 40 00 7F 00 41 F7" }, "74:${value}"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "Definition")]
+pub struct Macro {
+    pub steps: Vec<Step>
+}
+
+/// One entry of a macro.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Step {
+    /// Channel messages in the data syntax.
+    Data(String),
+    /// Hex SysEx messages.
+    SysEx { sysex: String }
+}
+
+// the forms a macro can be written in
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Definition {
+    Single(String),
+    Sequence(Vec<Step>)
+}
+
+impl From<Definition> for Macro {
+    fn from(definition: Definition) -> Macro {
+        match definition {
+            Definition::Single(data)    => Macro { steps: vec![Step::Data(data)] },
+            Definition::Sequence(steps) => Macro { steps }
+        }
+    }
+}
+
+impl Macro {
+    /// Substitute `arguments` into the macro and parse the result, ready to send.
+    ///
+    /// Every placeholder must have an argument, and every argument must be used by some
+    /// placeholder, so that a misspelt name is reported rather than silently ignored.
+    pub fn expand(&self, arguments: &Arguments, parser: &Parser) -> Result<Vec<Action>, Error> {
+        let mut used    = BTreeSet::new();
+        let mut actions = Vec::new();
+
+        for step in self.steps.iter() {
+            actions.push(match *step {
+                Step::Data(ref data) =>
+                    Action::Program(parser.parse(&substitute(data, arguments, &mut used)?)?),
+                Step::SysEx { ref sysex } =>
+                    Action::SysEx(sysex::parse_hex(&substitute(sysex, arguments, &mut used)?)?)
+            });
+        }
+
+        match arguments.keys().find(|name| !used.contains(name.as_str())) {
+            Some(name) => Err(Error::UnusedArgument(name.clone())),
+            None       => Ok(actions)
+        }
+    }
+}
+
+/// Parse command line macro arguments of the form `name=value`.
+///
+/// A single argument without a name is shorthand for `value=<argument>`.
+pub fn parse_arguments<S: AsRef<str>>(args: &[S]) -> Result<Arguments, Error> {
+    let mut arguments = Arguments::new();
+
+    for arg in args.iter().map(AsRef::as_ref) {
+        let (name, value) = match arg.find('=') {
+            Some(i) => (&arg[..i], &arg[i + 1..]),
+            None    => ("value", arg)
+        };
+
+        if name.is_empty() || arguments.insert(name.to_string(), value.to_string()).is_some() {
+            return Err(Error::InvalidArgument(arg.to_string()));
+        }
+    }
+
+    Ok(arguments)
+}
+
+// replace each `${name}` in `text`, recording which names were used
+fn substitute<'a>(text: &str, arguments: &'a Arguments, used: &mut BTreeSet<&'a str>)
+    -> Result<String, Error>
+{
+    let mut result = String::new();
+    let mut rest   = text;
+
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        let end  = rest.find('}').ok_or_else(|| Error::UnterminatedPlaceholder(text.to_string()))?;
+        let name = &rest[..end];
+        let (name, value) = arguments.get_key_value(name)
+            .ok_or_else(|| Error::MissingArgument(name.to_string()))?;
+
+        used.insert(name.as_str());
+        result.push_str(value);
+        rest = &rest[end + 1..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::parse::Program;
+
+    fn config() -> Config {
+        Config::parse(r#"
+            [macro]
+            local-off = "122:0"
+            init-patch = ["pc:5", { sysex = "F0 41 F7" }, "74:${value}"]
+            filter = "ch${channel}/74:${value}"
+        "#).unwrap()
+    }
+
+    fn args(list: &[&str]) -> Arguments {
+        parse_arguments(list).unwrap()
+    }
+
+    #[test]
+    fn parses_single_and_sequence_macros() {
+        let config = config();
+        assert_eq!(config.macros["local-off"].steps, vec![Step::Data("122:0".into())]);
+        assert_eq!(config.macros["init-patch"].steps, vec![
+            Step::Data("pc:5".into()),
+            Step::SysEx { sysex: "F0 41 F7".into() },
+            Step::Data("74:${value}".into())
+        ]);
+    }
+
+    #[test]
+    fn expands_with_arguments() {
+        let config  = config();
+        let actions = config.macros["init-patch"].expand(&args(&["100"]), &Parser::new()).unwrap();
+        assert_eq!(actions, vec![
+            Action::Program(Program::parse("pc:5").unwrap()),
+            Action::SysEx(sysex::parse_hex("F0 41 F7").unwrap()),
+            Action::Program(Program::parse("74:100").unwrap())
+        ]);
+
+        let actions = config.macros["filter"]
+            .expand(&args(&["channel=3", "value=7"]), &Parser::new())
+            .unwrap();
+        assert_eq!(actions, vec![Action::Program(Program::parse("ch3/74:7").unwrap())]);
+    }
+
+    #[test]
+    fn rejects_missing_unused_and_bad_arguments() {
+        let config = config();
+        let expand = |name: &str, list: &[&str]| config.macros[name].expand(&args(list), &Parser::new());
+
+        assert!(matches!(expand("init-patch", &[]), Err(Error::MissingArgument(ref n)) if n == "value"));
+        assert!(matches!(expand("local-off", &["1"]), Err(Error::UnusedArgument(ref n)) if n == "value"));
+        assert!(matches!(expand("init-patch", &["200"]), Err(Error::Parse(_))));
+
+        assert!(parse_arguments(&["1", "2"]).is_err());
+        assert!(parse_arguments(&["=1"]).is_err());
+        assert!(matches!(substitute("74:${value", &Arguments::new(), &mut BTreeSet::new()),
+                         Err(Error::UnterminatedPlaceholder(_))));
+    }
+}
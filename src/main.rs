@@ -1,182 +1,1040 @@
-#![feature(result_map_or_else)]
-
 extern crate structopt;
 
-use midir::{MidiOutput, MidiOutputConnection};
-use regex::Regex;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use cc_emitter::daemon;
+use cc_emitter::connections::Side;
+use cc_emitter::lfo;
+use cc_emitter::monitor::{self, Kind};
+use cc_emitter::parse::{self, Token};
+use cc_emitter::ramp;
+use cc_emitter::repl::{self, Line};
+use cc_emitter::sysex::{self, SysExError, SysExErrorKind};
+use cc_emitter::{connections, macros, ports, Channels, Config, Connections, ControllerNames, Device,
+                 Direction, Emitter, Error, Lfo, OutputSink, Parser, PortFilter, PortInfo,
+                 PortSelector, Waveform};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use structopt::clap::ErrorKind;
 use structopt::StructOpt;
 
 // program arguments
 #[derive(StructOpt)]
+#[structopt(after_help = "Running `cc-emitter <data>` is shorthand for `cc-emitter send <data>`.")]
 struct Opts {
-    /// Connect only to ports whose name contains a given string (defaults to connecting to all ports)
+    /// Enable extra verbosity. Defaults to disabled.
+    #[structopt(short = "v", long = "verbose", global = true)]
+    verbose: bool,
+
+    /// Read devices and macros from this config file instead of
+    /// ~/.config/cc-emitter/config.toml.
+    #[structopt(long = "config", parse(from_os_str), global = true)]
+    config: Option<PathBuf>,
+
+    /// Least time to leave between consecutive messages, such as 5ms, for devices which drop
+    /// messages that arrive too quickly.
+    #[structopt(long = "interval", parse(try_from_str = parse_duration), global = true)]
+    interval: Option<Duration>,
+
+    /// Socket of the daemon started with `cc-emitter daemon`, instead of
+    /// $XDG_RUNTIME_DIR/cc-emitter.sock.
+    #[structopt(long = "socket", parse(from_os_str), global = true)]
+    socket: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Command
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// List output ports and their names
+    #[structopt(name = "list")]
+    List {
+        /// Output format: text for people, or json or tsv for scripts. The machine-readable
+        /// formats include each port's number, name, ALSA client:port address (where available)
+        /// and direction.
+        #[structopt(short = "f", long = "format", default_value = "text",
+                    possible_values = &["text", "json", "tsv"])]
+        format: ListFormat,
+
+        /// List input ports as well as output ports.
+        #[structopt(short = "a", long = "all")]
+        all: bool
+    },
+
+    /// Send channel messages (the default when no subcommand is given)
+    #[structopt(name = "send")]
+    Send {
+        #[structopt(flatten)]
+        ports: PortOpts,
+
+        #[structopt(flatten)]
+        channels: ChannelOpts,
+
+        #[structopt(flatten)]
+        parsing: ParserOpts,
+
+        /// MIDI data to send, as messages separated by whitespace, commas or semicolons:
+        ///
+        /// <CC>:<Value> - Control Change (also cc:<CC>:<Value>)
+        ///
+        /// cc14:<CC>=<Value> - 14-bit Control Change, 0-16383, sent as MSB on <CC> (0-31) then
+        /// LSB on <CC>+32
+        ///
+        /// pc:<Program> - Program Change
+        ///
+        /// note:<Note>:<Velocity> - Note On
+        ///
+        /// off:<Note>[:<Velocity>] - Note Off
+        ///
+        /// poly:<Note>:<Pressure> - Polyphonic Aftertouch
+        ///
+        /// at:<Pressure> - Channel Aftertouch
+        ///
+        /// bend:<Value> - Pitch Bend, 0-16383 with 8192 as centre
+        ///
+        /// rpn:<MSB>,<LSB>=<Value> - RPN with 7-bit Data Entry (nrpn: for NRPN)
+        ///
+        /// rpn14:<MSB>,<LSB>=<Value> - RPN with 14-bit Data Entry, 0-16383 (nrpn14: for NRPN)
+        ///
+        /// <CC>:<Start>..<End>@<Time>[:<Curve>] - ramp the CC from Start to End over a time such as
+        /// 2s or 500ms, with a lin (default), exp or log curve. Later messages wait for the ramp.
+        ///
+        /// wait:<Time> - pause for a time such as 50ms before the next message
+        ///
+        /// All other numbers should be decimals within the range [0-127].
+        ///
+        /// Controllers may also be given by name, e.g. volume:100 or cc14:modwheel=8192, using
+        /// the standard MIDI names (modwheel, volume, pan, expression, sustain, local,
+        /// allnotesoff...) or aliases from the [alias] table of the config file.
+        ///
+        /// Prefix any message with ch<N>/ or ch<A>-<B>/ to send it only on those channels
+        /// instead, e.g. ch3/74:100.
+        ///
+        /// A # starts a comment running to the end of the line.
+        ///
+        /// Example: "70:104 74:124,pc:5" will send 104 to CC#70, 124 to #74, then Program
+        /// Change 5.
+        ///
+        /// Give - to read the data from stdin.
+        #[structopt(required_unless = "file")]
+        data: Option<String>,
+
+        /// Read the data from a file instead, which may span several lines and contain comments.
+        #[structopt(short = "f", long = "file", parse(from_os_str), conflicts_with = "data")]
+        file: Option<PathBuf>
+    },
+
+    /// Run a macro defined in the config file
+    #[structopt(name = "run")]
+    Run {
+        #[structopt(flatten)]
+        ports: PortOpts,
+
+        #[structopt(flatten)]
+        channels: ChannelOpts,
+
+        #[structopt(flatten)]
+        parsing: ParserOpts,
+
+        /// Name of the macro, from the [macro] table of the config file.
+        name: String,
+
+        /// Values for the macro's ${name} placeholders, given as name=value. A single value
+        /// without a name fills ${value}.
+        arguments: Vec<String>
+    },
+
+    /// Connect once, then send data typed at a prompt
+    #[structopt(name = "repl")]
+    Repl {
+        #[structopt(flatten)]
+        ports: PortOpts,
+
+        #[structopt(flatten)]
+        channels: ChannelOpts,
+
+        #[structopt(flatten)]
+        persist: PersistOpts,
+
+        #[structopt(flatten)]
+        parsing: ParserOpts
+    },
+
+    /// Keep ports open and send data received on a socket, so `send` doesn't have to connect
+    #[cfg(unix)]
+    #[structopt(name = "daemon")]
+    Daemon {
+        #[structopt(flatten)]
+        ports: PortOpts,
+
+        #[structopt(flatten)]
+        channels: ChannelOpts,
+
+        #[structopt(flatten)]
+        persist: PersistOpts,
+
+        #[structopt(flatten)]
+        parsing: ParserOpts
+    },
+
+    /// Silence stuck notes by sending All Sound Off, Reset All Controllers and All Notes Off
+    #[structopt(name = "panic")]
+    Panic {
+        #[structopt(flatten)]
+        ports: PortOpts,
+
+        #[structopt(flatten)]
+        channels: ChannelOpts,
+
+        /// Also send Note Off for every key, for devices which ignore All Notes Off.
+        #[structopt(long = "brute-force")]
+        brute_force: bool
+    },
+
+    /// Modulate a controller with an LFO until interrupted with Ctrl-C
+    #[structopt(name = "lfo")]
+    Lfo {
+        #[structopt(flatten)]
+        ports: PortOpts,
+
+        #[structopt(flatten)]
+        channels: ChannelOpts,
+
+        #[structopt(flatten)]
+        persist: PersistOpts,
+
+        /// Waveform: sine, triangle, square or random (a new random value each cycle).
+        #[structopt(short = "w", long = "wave", default_value = "sine",
+                    parse(try_from_str = parse_waveform))]
+        wave: Waveform,
+
+        /// Cycles per second, e.g. 0.5 for one cycle every two seconds.
+        #[structopt(long = "frequency", default_value = "1",
+                    parse(try_from_str = lfo::parse_frequency))]
+        frequency: f64,
+
+        /// How far the value swings either side of the offset.
+        #[structopt(long = "depth", default_value = "63", parse(try_from_str = lfo::parse_level))]
+        depth: f64,
+
+        /// The value the LFO swings around.
+        #[structopt(long = "offset", default_value = "64", parse(try_from_str = lfo::parse_level))]
+        offset: f64,
+
+        /// Value to set the controller to when stopped (defaults to the offset).
+        #[structopt(long = "rest")]
+        rest: Option<String>,
+
+        /// Messages per second sent while the LFO runs.
+        #[structopt(long = "update-rate", default_value = "50")]
+        update_rate: u32,
+
+        /// Controller to modulate, by number or name, e.g. 74 or cutoff.
+        controller: String
+    },
+
+    /// Print the messages arriving at input ports
+    #[structopt(name = "monitor")]
+    Monitor {
+        #[structopt(flatten)]
+        ports: PortOpts,
+
+        #[structopt(flatten)]
+        rescan: RescanOpts,
+
+        /// Only show channel messages on these channels, given as a list of channels and ranges
+        /// such as "1-4,10" (defaults to the device's channels, or all of them)
+        #[structopt(short = "c", long = "channel")]
+        channel: Option<Channels>,
+
+        /// Only show these kinds of message: note, poly, cc, pc, at, bend, sysex or system. May be
+        /// given more than once or as a list. Defaults to everything but system messages such as
+        /// clock.
+        #[structopt(short = "t", long = "type", use_delimiter = true,
+                    parse(try_from_str = parse_kind))]
+        types: Vec<Kind>
+    },
+
+    /// Pass messages from a device's input ports to output ports, transforming them by the rules
+    /// of a route in the config file, until interrupted with Ctrl-C
+    #[structopt(name = "route")]
+    Route {
+        #[structopt(flatten)]
+        ports: PortOpts,
+
+        #[structopt(flatten)]
+        persist: PersistOpts,
+
+        /// Listen to this device's input ports instead of the route's from device.
+        #[structopt(long = "from")]
+        from: Option<String>,
+
+        /// Name of the route, from the [route] table of the config file. Port options narrow the
+        /// route's to device, and --device replaces it.
+        name: String
+    },
+
+    /// Send System Exclusive messages, once to each port
+    #[structopt(name = "sysex")]
+    SysEx {
+        #[structopt(flatten)]
+        ports: PortOpts,
+
+        /// Send SysEx messages read from a .syx file. May be given more than once; files are
+        /// sent after any hex data, in the order given.
+        #[structopt(short = "f", long = "file", parse(from_os_str), number_of_values = 1)]
+        files: Vec<PathBuf>,
+
+        /// Milliseconds to wait between consecutive messages, for slow devices.
+        #[structopt(long = "delay", default_value = "0")]
+        delay: u64,
+
+        /// Hex SysEx messages to send, e.g. "F0 41 10 42 12 F7".
+        #[structopt(required_unless = "files")]
+        data: Option<String>
+    }
+}
+
+// how `list` prints ports
+enum ListFormat {
+    Text,
+    Json,
+    Tsv
+}
+
+impl FromStr for ListFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<ListFormat, String> {
+        match format {
+            "text" => Ok(ListFormat::Text),
+            "json" => Ok(ListFormat::Json),
+            "tsv"  => Ok(ListFormat::Tsv),
+            _      => Err(format!("unknown format {}", format))
+        }
+    }
+}
+
+impl Command {
+    // read the data argument the command parses, from the command line, stdin or a file. It's
+    // kept to point out where an error is.
+    fn read_data(&self) -> Result<Option<String>, Error> {
+        match *self {
+            Command::Send { file: Some(ref path), .. } => fs::read_to_string(path)
+                .map(Some)
+                .map_err(|error| Error::Io { path: path.clone(), error }),
+            Command::Send { data: Some(ref data), .. } if data == "-" => {
+                let mut data = String::new();
+                io::stdin().read_to_string(&mut data)
+                    .map_err(|error| Error::Io { path: PathBuf::from("<stdin>"), error })?;
+                Ok(Some(data))
+            }
+            Command::Send { ref data, .. }  => Ok(data.clone()),
+            Command::SysEx { ref data, .. } => Ok(data.clone()),
+            _                               => Ok(None)
+        }
+    }
+}
+
+// options shared by every command which connects to output ports
+#[derive(StructOpt)]
+struct PortOpts {
+    /// Use the ports and channels of a device defined in the config file. Other port options
+    /// narrow the device's ports further, and --channel replaces its channels.
+    #[structopt(short = "d", long = "device")]
+    device: Option<String>,
+
+    /// Connect only to ports whose name contains a given string (defaults to connecting to all
+    /// ports)
     #[structopt(short = "p", long = "port")]
     port_filter: Option<String>,
 
-    /// Send messages on only a specific channel (defaults to sending to all 16 channels)
-    #[structopt(short = "c", long = "channel")]
-    channel: Option<u8>,
+    /// Connect only to ports whose name matches a regular expression
+    #[structopt(long = "port-regex")]
+    port_regex: Option<String>,
 
-    /// Enable extra verbosity. Defaults to disabled.
-    #[structopt(short = "v", long = "verbose")]
-    verbose: bool,
+    /// Connect only to the port with exactly this name
+    #[structopt(long = "port-exact")]
+    port_exact: Option<String>,
 
-    /// List ports and their names, then exit. Due to programmer laziness, you also have to supply
-    /// a dummy data value to make the argument parser work correctly. Anything will do, e.g.
-    /// `cc-emitter -l a`
-    #[structopt(short = "l", long = "list")]
-    list_ports: bool,
+    /// Connect only to the port with this number, as shown by `list`
+    #[structopt(long = "port-index")]
+    port_index: Option<usize>,
 
-    /// MIDI CC data to send, in the following format:
-    ///
-    /// (<CC>:<Value>[^:0-9]*)+
+    /// Skip ports whose name contains a given string. May be given more than once.
     ///
-    /// That is, the CC number and value should be joined by :, and separated from each other by
-    /// any other character.
-    ///
-    /// Both the CC number and value should be decimals within the range [0-127].
-    ///
-    /// Example: "70:104 74:124,122:0" will send 104 to CC#104, 124 to #74, 0 to #122, etc.
-    data: String
+    /// All port options can be combined: a port is used only if it matches every --port,
+    /// --port-regex, --port-exact and --port-index given, and no --exclude-port.
+    #[structopt(short = "P", long = "exclude-port", number_of_values = 1)]
+    exclude_port: Vec<String>
 }
 
-// Display name for output port
-const OUTPUT_PORT_NAME: &'static str = "@selenologist CC emitter";
-// Name to be displayed on connections
-const OUTPUT_CONNECTION_NAME: &'static str = "@selenologist CC emitter connection";
+impl PortOpts {
+    // look up the device named by --device, if any
+    fn device<'a>(&self, config: &'a Config) -> Result<Option<&'a Device>, Error> {
+        match self.device {
+            Some(ref name) => Ok(Some(config.device(name)?)),
+            None           => Ok(None)
+        }
+    }
 
-// MIDI protocol constants
-const CONTROL_CHANGE_PREFIX: u8 = 0xB0;
+    // whether no port options were given at all
+    fn selects_all(&self) -> bool {
+        self.device.is_none() && self.port_filter.is_none() && self.port_regex.is_none()
+            && self.port_exact.is_none() && self.port_index.is_none() && self.exclude_port.is_empty()
+    }
 
-fn main() {
-    // parse program arguments
-    let opts = Opts::from_args();
-    
-    // compile regex for parsing CC input
-    let cc_regex = Regex::new(r"([0-9]+):([0-9]+)").expect("Failed to create CC regex");
-
-    // convert CC input string into (CC, Value) u8 pairs
-    let data: Vec<(u8, u8)> = cc_regex
-        .captures_iter(opts.data.as_str())
-        .map(|cap| {
-            let str_to_u8 = |s: &str| {
-                let i = s
-                    .parse::<isize>()
-                    .unwrap_or_else(|_| panic!("Data value '{}' is could not be parsed.", s));
-
-                // if the value is out of the unsigned 8-bit range
-                if i < 0 || i > 255 {
-                    // note, this program will happily attempt to send CCs greater than 127
-                    // what happens to the output when you do this is undefined.
-                    panic!("Data value '{}' is out of range.", i);
-                }
-                else {
-                    i as u8
-                }
-            };
+    // the controller names to use: the device's, if one was given, or the config's aliases
+    fn names(&self, config: &Config) -> Result<ControllerNames, Error> {
+        match self.device(config)? {
+            Some(device) => device.names(&config.names),
+            None         => Ok(config.names.clone())
+        }
+    }
+
+    // build the port selector from the device and all the port filtering options
+    fn selector(&self, device: Option<&Device>) -> Result<PortSelector, Error> {
+        let mut ports = match device {
+            Some(device) => device.selector()?,
+            None         => PortSelector::all()
+        };
+
+        if let Some(ref filter) = self.port_filter {
+            ports = ports.include(PortFilter::Contains(filter.clone()));
+        }
+        if let Some(ref pattern) = self.port_regex {
+            ports = ports.include(PortFilter::regex(pattern)?);
+        }
+        if let Some(ref name) = self.port_exact {
+            ports = ports.include(PortFilter::Exact(name.clone()));
+        }
+        if let Some(index) = self.port_index {
+            ports = ports.include(PortFilter::Index(index));
+        }
+        for filter in self.exclude_port.iter() {
+            ports = ports.exclude(PortFilter::Contains(filter.clone()));
+        }
+
+        Ok(ports)
+    }
+}
+
+// options shared by every command which sends channel messages
+#[derive(StructOpt)]
+struct ChannelOpts {
+    /// Send messages on only specific channels, given as a list of channels and ranges such as
+    /// "1-4,10" (defaults to sending to all 16 channels)
+    #[structopt(short = "c", long = "channel")]
+    channel: Option<String>
+}
 
-            let cc    = cap.get(1).unwrap().as_str();
-            let value = cap.get(2).unwrap().as_str();
+impl ChannelOpts {
+    fn channels(&self, device: Option<&Device>) -> Result<Channels, Error> {
+        match self.channel {
+            Some(ref spec) => Channels::parse(spec).map_err(|error| Error::Argument {
+                name: "--channel", value: spec.clone(), error
+            }),
+            None => Ok(device.and_then(|device| device.channel).unwrap_or_else(Channels::all))
+        }
+    }
+}
+
+// options shared by every command which parses data
+#[derive(StructOpt)]
+struct ParserOpts {
+    /// Select the RPN null parameter after each RPN or NRPN change, so stray Data Entry
+    /// messages can't alter it afterwards.
+    #[structopt(long = "rpn-null")]
+    rpn_null: bool,
+
+    /// Messages per second sent by ramps.
+    #[structopt(long = "rate", default_value = "50")]
+    rate: u32
+}
+
+impl ParserOpts {
+    fn parser(&self, names: ControllerNames) -> Parser {
+        Parser::new().rpn_null(self.rpn_null).names(names).ramp_rate(self.rate)
+    }
+
+    // whether the parser behaves as the daemon's does
+    fn is_default(&self) -> bool {
+        !self.rpn_null && self.rate == ramp::DEFAULT_RATE
+    }
+}
+
+// how often the commands which keep ports open look for them coming and going
+#[derive(StructOpt)]
+struct RescanOpts {
+    /// How often to look for ports being plugged in or unplugged, such as 2s or 500ms. 0s only
+    /// looks once, at the start.
+    #[structopt(long = "rescan", default_value = "2s", parse(try_from_str = parse_duration))]
+    period: Duration
+}
 
-            (str_to_u8(cc), str_to_u8(value))
-        })
-        .collect();
+impl RescanOpts {
+    // scan for ports now, and keep rescanning in the background unless that's turned off
+    fn watch<S>(&self, mut connections: Connections<S>) -> Result<Arc<Mutex<Connections<S>>>, Error>
+        where S: Side + Send + 'static
+    {
+        connections.rescan()?;
 
-    // emit data on the specified channels for a given connection
-    let do_conn = |mut conn: MidiOutputConnection| {
-        let mut do_channel = |channel: u8| {
-            for (cc, value) in data.iter() {
-                if opts.verbose {
-                    println!("Sending CC#{} value {} on ch#{}", cc, value, channel+1);
+        let connections = Arc::new(Mutex::new(connections));
+        if self.period > Duration::from_secs(0) {
+            connections::watch(connections.clone(), self.period);
+        }
+        Ok(connections)
+    }
+}
+
+// options shared by the commands which keep output ports open
+#[derive(StructOpt)]
+struct PersistOpts {
+    #[structopt(flatten)]
+    rescan: RescanOpts,
+
+    /// Macro to run on each port as it's connected, to set up freshly attached devices (defaults
+    /// to the device's on-connect macro).
+    #[structopt(long = "on-connect")]
+    on_connect: Option<String>
+}
+
+// connect to the emitter's ports, running the on-connect macro (or the device's) on each, and keep
+// the connections up to date as devices come and go
+fn connect(config: &Config, device: Option<&Device>, persist: &PersistOpts, emitter: &Emitter,
+           parser: &Parser)
+    -> Result<Arc<Mutex<Connections>>, Error>
+{
+    let actions = match persist.on_connect.as_ref().or(device.and_then(|d| d.on_connect.as_ref())) {
+        Some(name) => config.expand_macro(name, &macros::Arguments::new(), parser)?,
+        None       => Vec::new()
+    };
+
+    let setup = emitter.clone();
+    let connections = Connections::new(emitter.ports.clone())
+        .verbose(emitter.verbose)
+        .on_connect(move |name, conn| {
+            if !actions.is_empty() {
+                if setup.verbose {
+                    println!("Running the on-connect macro on \"{}\"", name);
                 }
-                
-                conn.send(&[CONTROL_CHANGE_PREFIX | channel, *cc, *value])
-                    .unwrap_or_else(|e| eprintln!("Failed to send CC#{} value {} on ch#{}: {:?}",
-                                                  cc, value, channel+1, e));
+                setup.emit_actions_to(conn, &actions);
             }
-        };
+        });
+
+    persist.rescan.watch(connections)
+}
+
+fn lock<S: Side>(connections: &Mutex<Connections<S>>) -> MutexGuard<'_, Connections<S>> {
+    connections.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
-        // if a specific channel was supplied as an argument, only do that channel
-        if let Some(specified_channel) = opts.channel {
-            // convert from human 1-based channel index, to 0-based indexing.
-            let channel = match specified_channel {
-                // treat input of 0 as being synonymous with channel 1
-                0      => 0,
-                // if between 1 and 16, subtract 1 to convert to zero-indexed
-                1..=16 => specified_channel - 1,
-                // otherwise an invalid channel was specified, panic.
-                _      => panic!("Channel {} exceeds maximum of 16", specified_channel)
+// read the config file given with --config, or the default one
+fn load_config(path: &Option<PathBuf>) -> Result<Config, Error> {
+    match *path {
+        Some(ref path) => Config::load(path),
+        None           => Config::load_default()
+    }
+}
+
+// build an emitter from the port and channel options, sending on all channels if there are no
+// channel options
+fn emitter(opts: &Opts, config: &Config, ports: &PortOpts, channels: Option<&ChannelOpts>,
+           names: ControllerNames)
+    -> Result<Emitter, Error>
+{
+    let device   = ports.device(config)?;
+    let channels = match channels {
+        Some(channels) => channels.channels(device)?,
+        None           => Channels::all()
+    };
+
+    Ok(Emitter::new(ports.selector(device)?, channels)
+        .verbose(opts.verbose)
+        .interval(opts.interval.unwrap_or_default())
+        .names(names))
+}
+
+// send data through a running daemon instead of connecting, returning whether it was sent. Only
+// data using the daemon's own ports, pacing, config and parser settings can be forwarded.
+#[cfg(unix)]
+fn forward(opts: &Opts, ports: &PortOpts, channels: &ChannelOpts, parsing: &ParserOpts,
+           data: &str)
+    -> Result<bool, Error>
+{
+    let daemon_settings = ports.selects_all() && parsing.is_default() && opts.interval.is_none()
+        && opts.config.is_none();
+    if !daemon_settings {
+        return Ok(false);
+    }
+
+    let mut lines = Vec::new();
+    if let Some(ref spec) = channels.channel {
+        lines.push(format!(".channel {}", spec));
+    }
+    // the daemon reads a line at a time, so put the whole program on one, without its comments
+    lines.push(parse::tokens(data).map(|token| token.text).collect::<Vec<_>>().join(" "));
+
+    let path = opts.socket.clone().unwrap_or_else(daemon::socket_path);
+    let sent = daemon::forward(&path, &lines)?;
+    if sent && opts.verbose {
+        println!("Sent through the daemon on {}", path.display());
+    }
+    Ok(sent)
+}
+
+#[cfg(not(unix))]
+fn forward(_: &Opts, _: &PortOpts, _: &ChannelOpts, _: &ParserOpts, _: &str)
+    -> Result<bool, Error>
+{
+    Ok(false)
+}
+
+// parse a duration argument using the same syntax as the data
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    Token { offset: 0, text }.duration().map_err(|e| e.to_string())
+}
+
+fn parse_waveform(name: &str) -> Result<Waveform, String> {
+    Waveform::from_name(name).ok_or_else(|| format!("unknown waveform {}", name))
+}
+
+fn parse_kind(name: &str) -> Result<Kind, String> {
+    Kind::from_name(name).ok_or_else(|| format!("unknown message type {}", name))
+}
+
+// parse the program arguments, treating a missing subcommand as `send`
+fn parse_args() -> Opts {
+    let args: Vec<OsString> = env::args_os().collect();
+
+    match Opts::from_iter_safe(&args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            let unrecognised = |kind| {
+                matches!(kind, ErrorKind::UnrecognizedSubcommand | ErrorKind::UnknownArgument)
             };
+            if !unrecognised(e.kind) {
+                e.exit();
+            }
 
-            do_channel(channel as u8)
-        }
-        // otherwise, send to all channels
-        else {
-            (0u8..16).for_each(do_channel)
+            // `cc-emitter [options] <data>` doesn't name a subcommand, so try again as `send`
+            let mut send_args = args.clone();
+            send_args.insert(1.min(args.len()), "send".into());
+
+            match Opts::from_iter_safe(send_args) {
+                Ok(opts) => opts,
+                // if `send` got further, its complaint is the useful one
+                Err(send_error) if !unrecognised(send_error.kind) => send_error.exit(),
+                Err(_) => e.exit()
+            }
         }
+    }
+}
+
+// tab completion of commands, keywords and controller names at the REPL prompt
+struct ReplHelper {
+    names: ControllerNames
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(repl::complete(line, pos, &self.names))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+impl Validator for ReplHelper {}
+impl Helper for ReplHelper {}
+
+// report a problem with the terminal as failing to read stdin
+fn terminal_error(e: ReadlineError) -> Error {
+    let error = match e {
+        ReadlineError::Io(error) => error,
+        e                        => io::Error::other(e)
     };
+    Error::Io { path: PathBuf::from("<stdin>"), error }
+}
 
-    // create a MIDI output
-    let make_output = ||
-        MidiOutput::new(OUTPUT_PORT_NAME)
-            .expect("Failed to open MIDI output");
+// connect once, then send each line typed at the prompt until the user quits
+fn run_repl(emitter: &mut Emitter, parser: &Parser, connections: &Mutex<Connections>)
+    -> Result<(), Error>
+{
+    let mut editor = Editor::<ReplHelper, DefaultHistory>::new().map_err(terminal_error)?;
+    editor.set_helper(Some(ReplHelper { names: parser.names.clone() }));
 
-    let mut output = make_output();
+    // there's no history the first time round
+    let history = repl::history_path();
+    if let Some(ref path) = history {
+        let _ = editor.load_history(path);
+    }
 
-    // if the list_ports flag is set, list ports then exit
-    if opts.list_ports {
-        for port in 0..output.port_count() {
-            match output.port_name(port) {
-                Ok(name) => println!("Port #{}: \"{}\"", port, name),
-                Err(e)   => eprintln!("Failed to get port #{} name: {:?}.",
-                                      port, e)
+    println!("Connected to {} port(s). Type .help for commands.", lock(connections).len());
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line)                        => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof)         => break,
+            Err(e)                          => return Err(terminal_error(e))
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+
+        match Line::parse(&line, parser).and_then(|parsed| repl_line(parsed, emitter, connections)) {
+            Ok(true)  => (),
+            Ok(false) => break,
+            Err(e)    => report_error(&e, Some(&line))
+        }
+    }
+
+    if let Some(ref path) = history {
+        let saved = path.parent().map_or(Ok(()), fs::create_dir_all).map_err(ReadlineError::Io)
+            .and_then(|_| editor.save_history(path));
+        if let Err(e) = saved {
+            eprintln!("Failed to save history to {}: {}", path.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+// act on one line of REPL input, returning whether to carry on
+fn repl_line(line: Line, emitter: &mut Emitter, connections: &Mutex<Connections>)
+    -> Result<bool, Error>
+{
+    match line {
+        Line::Empty         => (),
+        Line::Data(program) => emitter.emit_to(&mut &*connections, &program),
+        Line::Port(filter)  => {
+            emitter.ports = filter.map_or_else(PortSelector::all,
+                                               |filter| PortSelector::all().include(filter));
+            reselect(emitter, connections)?;
+        }
+        Line::Exclude(filter) => {
+            match filter {
+                Some(filter) => emitter.ports.exclude.push(filter),
+                None         => emitter.ports.exclude.clear()
             }
+            reselect(emitter, connections)?;
         }
-        return;
-    }
-
-    // connect to each available port, filtering by name if specified
-    //
-    // note: the interface of midir, just like most underlying platform APIs, is inherently prone
-    // to race conditions regarding the port number.
-    // Hopefully the program completes fast enough that this doesn't cause annoying effects in
-    // practice. The program will not crash at least.
-    for port in 0..output.port_count() {
-        // get the port's name
-        let name = match output.port_name(port) {
-            Ok(n) => n,
-            Err(e) => {
-                eprintln!("Failed to get port #{} name: {:?}. Skipping this port.", port, e);
-                continue;
+        Line::Channel(channels) => emitter.channels = channels,
+        Line::Ports => for name in lock(connections).names() {
+            println!("\"{}\"", name);
+        },
+        Line::Help => {
+            println!("Type data to send it, e.g. 74:100 pc:5, or one of these commands:");
+            for &(command, help) in repl::COMMANDS.iter() {
+                println!("  {:<12} {}", command, help);
             }
-        };
+        }
+        Line::Quit => return Ok(false)
+    }
+
+    Ok(true)
+}
+
+// switch the connections over to the emitter's ports
+fn reselect(emitter: &Emitter, connections: &Mutex<Connections>) -> Result<(), Error> {
+    let mut connections = lock(connections);
+    connections.select(emitter.ports.clone());
+    connections.rescan()?;
+    println!("Connected to {} port(s).", connections.len());
+    Ok(())
+}
+
+// print an error, pointing out where it is in the data if it's a parse error
+fn report_error(e: &Error, data: Option<&str>) {
+    eprintln!("{}", e);
+    match (e, data) {
+        (Error::Parse(e), Some(data))             => show_error_location(data, e.offset),
+        (Error::Argument { value, error, .. }, _) => show_error_location(value, error.offset),
+        _                                         => ()
+    }
+}
+
+// print where in the data a parse error occurred
+fn show_error_location(data: &str, offset: usize) {
+    // show just the line holding the error, numbered if the data has more than one
+    let start  = data[..offset].rfind('\n').map_or(0, |n| n + 1);
+    let end    = data[offset..].find('\n').map_or(data.len(), |n| offset + n);
+    let column = data[start..offset].chars().count();
+
+    if data.contains('\n') {
+        let line   = data[..offset].matches('\n').count() + 1;
+        let prefix = format!("  line {}: ", line);
+        eprintln!("{}{}", prefix, &data[start..end]);
+        eprintln!("{}{}^", " ".repeat(prefix.len()), " ".repeat(column));
+    }
+    else {
+        eprintln!("  {}", data);
+        eprintln!("  {}^", " ".repeat(column));
+    }
+}
+
+// gather port descriptions, skipping ports whose name can't be read
+fn port_infos(all: bool) -> Result<Vec<PortInfo>, Error> {
+    let mut names = ports::list(&ports::make_output()?).into_iter()
+        .map(|(port, name)| (port, name, Direction::Output))
+        .collect::<Vec<_>>();
+
+    if all {
+        names.extend(ports::list_inputs(&ports::make_input()?).into_iter()
+            .map(|(port, name)| (port, name, Direction::Input)));
+    }
+
+    let mut infos = Vec::new();
+    for (port, name, direction) in names {
+        match name {
+            Ok(name) => infos.push(PortInfo::new(port, name, direction)),
+            Err(e)   => eprintln!("Failed to get {} port #{} name: {:?}.",
+                                  direction, port, e)
+        }
+    }
+
+    Ok(infos)
+}
+
+fn list_ports(format: &ListFormat, all: bool) -> Result<(), Error> {
+    let infos = port_infos(all)?;
+
+    match *format {
+        ListFormat::Text => for info in infos.iter() {
+            match info.direction {
+                Direction::Output => println!("Port #{}: \"{}\"", info.index, info.name),
+                Direction::Input  => println!("Input port #{}: \"{}\"", info.index, info.name)
+            }
+        },
+        ListFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&infos).expect("port list serializes"));
+        }
+        ListFormat::Tsv => {
+            println!("index\tname\tid\tdirection");
+            for info in infos.iter() {
+                // keep each port on one line of four fields, whatever its name holds
+                let name = info.name.replace(['\t', '\n'], " ");
+                println!("{}\t{}\t{}\t{}", info.index, name,
+                         info.id.as_ref().map_or("", String::as_str), info.direction);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn run(opts: &Opts, data: Option<&str>) -> Result<(), Error> {
+    match opts.command {
+        Command::List { ref format, all } => list_ports(format, all),
+
+        Command::Send { ref ports, ref channels, ref parsing, .. } => {
+            let config  = load_config(&opts.config)?;
+            let names   = ports.names(&config)?;
+            let program = parsing.parser(names.clone()).parse(data.unwrap_or_default())?;
+
+            // the data's checked first, so mistakes are pointed out just as when sending directly
+            if forward(opts, ports, channels, parsing, data.unwrap_or_default())? {
+                return Ok(());
+            }
+
+            emitter(opts, &config, ports, Some(channels), names)?.run(&program)
+        }
+
+        Command::Run { ref ports, ref channels, ref parsing, ref name, ref arguments } => {
+            let config  = load_config(&opts.config)?;
+            let names   = ports.names(&config)?;
+            let parser  = parsing.parser(names.clone());
+            let actions = config.expand_macro(name, &macros::parse_arguments(arguments)?, &parser)?;
+
+            emitter(opts, &config, ports, Some(channels), names)?.run_actions(&actions)
+        }
+
+        Command::Repl { ref ports, ref channels, ref persist, ref parsing } => {
+            let config      = load_config(&opts.config)?;
+            let names       = ports.names(&config)?;
+            let parser      = parsing.parser(names.clone());
+            let mut emitter = emitter(opts, &config, ports, Some(channels), names)?;
+
+            let connections = connect(&config, ports.device(&config)?, persist, &emitter, &parser)?;
+            run_repl(&mut emitter, &parser, &connections)
+        }
+
+        #[cfg(unix)]
+        Command::Daemon { ref ports, ref channels, ref persist, ref parsing } => {
+            let config  = load_config(&opts.config)?;
+            let names   = ports.names(&config)?;
+            let parser  = parsing.parser(names.clone());
+            let emitter = emitter(opts, &config, ports, Some(channels), names)?;
+
+            let path     = opts.socket.clone().unwrap_or_else(daemon::socket_path);
+            let listener = daemon::listen(&path)?;
+            let connections = connect(&config, ports.device(&config)?, persist, &emitter, &parser)
+                .inspect_err(|_| {
+                    let _ = fs::remove_file(&path);
+                })?;
+
+            // take the socket away when stopped, so clients go back to sending directly
+            let socket = path.clone();
+            ctrlc::set_handler(move || {
+                let _ = fs::remove_file(&socket);
+                process::exit(0);
+            })?;
 
-        // if a name filter is set, check if the port name matches it
-        if let Some(ref filter) = opts.port_filter {
-            if !name.contains(filter) {
-                if opts.verbose {
-                    println!("Skipping port #{} \"{}\" because it doesn't contain \"{}\"",
-                         port, name, filter);
+            println!("Connected to {} port(s). Listening on {}", lock(&connections).len(),
+                     path.display());
+            daemon::serve(&listener, &connections, &emitter, &parser);
+            Ok(())
+        }
+
+        Command::Panic { ref ports, ref channels, brute_force } => {
+            let config = load_config(&opts.config)?;
+            emitter(opts, &config, ports, Some(channels), ports.names(&config)?)?
+                .run(&cc_emitter::panic::program(brute_force))
+        }
+
+        Command::Lfo { ref ports, ref channels, ref persist, wave, frequency, depth, offset, ref rest,
+                       update_rate, ref controller } => {
+            let config     = load_config(&opts.config)?;
+            let names      = ports.names(&config)?;
+            let parser     = Parser::new().names(names.clone());
+            let controller = parser.parse_controller(controller)
+                .map_err(|error| Error::Argument {
+                    name: "controller", value: controller.clone(), error
+                })?;
+            let rest       = match *rest {
+                Some(ref rest) => Token { offset: 0, text: rest }.data_byte()
+                    .map_err(|error| Error::Argument { name: "--rest", value: rest.clone(), error })?,
+                None           => offset.round().clamp(0.0, 127.0) as u8
+            };
+
+            // stop at the end of the current update, so the resting value is still sent
+            let stop = Arc::new(AtomicBool::new(false));
+            let handler_stop = stop.clone();
+            ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst))?;
+
+            let emitter     = emitter(opts, &config, ports, Some(channels), names)?;
+            let connections = connect(&config, ports.device(&config)?, persist, &emitter, &parser)?;
+
+            let mut lfo = Lfo::new(controller, wave, frequency, depth, offset);
+            emitter.emit_lfo_to(&mut &*connections, &mut lfo, update_rate, Some(rest), &stop);
+            Ok(())
+        }
+
+        Command::Monitor { ref ports, ref rescan, channel, ref types } => {
+            let config   = load_config(&opts.config)?;
+            let device   = ports.device(&config)?;
+            let channels = channel.or_else(|| device.and_then(|device| device.channel))
+                .unwrap_or_else(Channels::all);
+            let filter   = monitor::Filter::new(types.clone(), channels);
+            let names    = ports.names(&config)?;
+            let start    = Instant::now();
+
+            let selector = ports.selector(device)?;
+
+            let show   = move |port: &str, message: &[u8]| {
+                if filter.accepts(message) {
+                    println!("{}", monitor::describe(start.elapsed(), port, message, &names));
                 }
-                continue;
+            };
+            let inputs = rescan.watch(Connections::inputs(selector, show).verbose(opts.verbose))?;
+            eprintln!("Listening to {} input port(s). Press Ctrl-C to stop.", lock(&inputs).len());
+
+            // messages are printed on midir's threads until the program is interrupted
+            loop {
+                thread::park();
             }
         }
 
-        if opts.verbose {
-            println!("Connecting to port #{} \"{}\"", port, name);
+        Command::Route { ref ports, ref persist, ref from, ref name } => {
+            let config = load_config(&opts.config)?;
+            let route  = config.route(name)?;
+            let from   = config.device(from.as_ref().unwrap_or(&route.from))?;
+            let to     = match ports.device(&config)? {
+                Some(device) => Some(device),
+                None         => route.to.as_ref().map(|to| config.device(to)).transpose()?
+            };
+
+            let names  = match to {
+                Some(device) => device.names(&config.names)?,
+                None         => config.names.clone()
+            };
+            let router = route.router(&from.names(&config.names)?, &names)
+                .map_err(|reason| Error::Route { name: name.clone(), reason })?;
+
+            let parser  = Parser::new().names(names.clone());
+            let emitter = Emitter::new(ports.selector(to)?, Channels::all())
+                .verbose(opts.verbose)
+                .interval(opts.interval.unwrap_or_default())
+                .names(names);
+            let connections = connect(&config, to, persist, &emitter, &parser)?;
+            let outputs     = lock(&connections).len();
+
+            let pass   = move |_: &str, message: &[u8]| {
+                if let Some(message) = router.apply(message) {
+                    let _ = lock(&connections).send(&message);
+                }
+            };
+            let inputs = persist.rescan.watch(Connections::inputs(from.selector()?, pass)
+                                              .verbose(opts.verbose))?;
+            eprintln!("Routing {} input port(s) to {} output port(s). Press Ctrl-C to stop.",
+                      lock(&inputs).len(), outputs);
+
+            // messages are passed on from midir's threads until the program is interrupted
+            loop {
+                thread::park();
+            }
+        }
+
+        Command::SysEx { ref ports, ref files, delay, .. } => {
+            let config = load_config(&opts.config)?;
+            let mut messages = sysex::parse_hex(data.unwrap_or_default())?;
+            for path in files.iter() {
+                messages.extend(sysex::read_syx(path)?);
+            }
+
+            if messages.is_empty() {
+                return Err(SysExError::new(0, SysExErrorKind::Empty).into());
+            }
+
+            emitter(opts, &config, ports, None, config.names.clone())?
+                .run_sysex(&messages, Duration::from_millis(delay))
         }
+    }
+}
+
+fn main() {
+    let opts = parse_args();
 
-        // hack: MidiOutput.connect consumes the MidiOutput, but a MidiOutput is necessary to
-        // query the ports! So, replace the old MidiOutput with a new one so we can take ownership
-        // of the old one.
-        // This creates a wasted extra MidiOutput at the end of the loop but meh.
-        // Avoids having to write an even messier way of maintaining already-done port information.
-        // Doing this at this point also avoids recreating output if we skipped the current port.
-        let current_output = std::mem::replace(&mut output, make_output()); 
+    // the data is kept here so errors in it can be pointed out
+    let mut data   = None;
+    let result     = opts.command.read_data().and_then(|read| {
+        data = read;
+        run(&opts, data.as_deref())
+    });
 
-        current_output
-            .connect(port, OUTPUT_CONNECTION_NAME)
-            .map_or_else(|e| eprintln!("Failed to connect to port#{} \"{}\": {:?}", port, name, e),
-                         do_conn);
+    if let Err(e) = result {
+        report_error(&e, data.as_deref());
+        process::exit(e.exit_code());
     }
 }
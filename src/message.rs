@@ -0,0 +1,464 @@
+use std::fmt;
+
+use crate::names::ControllerNames;
+
+// MIDI protocol constants
+pub const NOTE_OFF_PREFIX: u8           = 0x80;
+pub const NOTE_ON_PREFIX: u8            = 0x90;
+pub const POLY_AFTERTOUCH_PREFIX: u8    = 0xA0;
+pub const CONTROL_CHANGE_PREFIX: u8     = 0xB0;
+pub const PROGRAM_CHANGE_PREFIX: u8     = 0xC0;
+pub const CHANNEL_AFTERTOUCH_PREFIX: u8 = 0xD0;
+pub const PITCH_BEND_PREFIX: u8         = 0xE0;
+
+/// Well-known controller numbers.
+pub mod cc {
+    pub const DATA_ENTRY_MSB: u8 = 6;
+    pub const DATA_ENTRY_LSB: u8 = 38;
+    pub const NRPN_LSB: u8       = 98;
+    pub const NRPN_MSB: u8       = 99;
+    pub const RPN_LSB: u8        = 100;
+    pub const RPN_MSB: u8        = 101;
+
+    pub const ALL_SOUND_OFF: u8         = 120;
+    pub const RESET_ALL_CONTROLLERS: u8 = 121;
+    pub const LOCAL_CONTROL: u8         = 122;
+    pub const ALL_NOTES_OFF: u8         = 123;
+
+    /// Controllers 0-31 are paired with an LSB controller at this offset (32-63).
+    pub const LSB_OFFSET: u8     = 32;
+    /// Highest controller number with an LSB partner.
+    pub const HIGH_RES_MAX: u8   = 31;
+}
+
+/// Largest value of a 14-bit quantity such as pitch bend.
+pub const VALUE14_MAX: u16 = 0x3FFF;
+/// Pitch bend value meaning "no bend".
+pub const PITCH_BEND_CENTER: u16 = 0x2000;
+
+// status byte for a channel voice message
+fn status(prefix: u8, channel: u8) -> u8 {
+    prefix | (channel & 0x0F)
+}
+
+// split a 14-bit value into its (LSB, MSB) 7-bit data bytes, in wire order
+fn split14(value: u16) -> (u8, u8) {
+    ((value & 0x7F) as u8, ((value >> 7) & 0x7F) as u8)
+}
+
+/// A single MIDI Control Change message.
+///
+/// `channel` is zero-based (0-15), as it appears on the wire. The same goes for every message
+/// type in this module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlChange {
+    pub channel:    u8,
+    pub controller: u8,
+    pub value:      u8
+}
+
+impl ControlChange {
+    pub fn new(channel: u8, controller: u8, value: u8) -> ControlChange {
+        ControlChange { channel, controller, value }
+    }
+
+    /// Returns a copy of this message addressed to a different (zero-based) channel.
+    pub fn on_channel(self, channel: u8) -> ControlChange {
+        ControlChange { channel, ..self }
+    }
+
+    /// Encode the message as the raw bytes to send to a port.
+    pub fn to_bytes(&self) -> [u8; 3] {
+        [status(CONTROL_CHANGE_PREFIX, self.channel), self.controller, self.value]
+    }
+}
+
+/// A Note On message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteOn {
+    pub channel:  u8,
+    pub note:     u8,
+    pub velocity: u8
+}
+
+impl NoteOn {
+    pub fn to_bytes(&self) -> [u8; 3] {
+        [status(NOTE_ON_PREFIX, self.channel), self.note, self.velocity]
+    }
+}
+
+/// A Note Off message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteOff {
+    pub channel:  u8,
+    pub note:     u8,
+    pub velocity: u8
+}
+
+impl NoteOff {
+    pub fn to_bytes(&self) -> [u8; 3] {
+        [status(NOTE_OFF_PREFIX, self.channel), self.note, self.velocity]
+    }
+}
+
+/// A Polyphonic Key Pressure (per-note aftertouch) message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolyAftertouch {
+    pub channel:  u8,
+    pub note:     u8,
+    pub pressure: u8
+}
+
+impl PolyAftertouch {
+    pub fn to_bytes(&self) -> [u8; 3] {
+        [status(POLY_AFTERTOUCH_PREFIX, self.channel), self.note, self.pressure]
+    }
+}
+
+/// A Program Change message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramChange {
+    pub channel: u8,
+    pub program: u8
+}
+
+impl ProgramChange {
+    pub fn to_bytes(&self) -> [u8; 2] {
+        [status(PROGRAM_CHANGE_PREFIX, self.channel), self.program]
+    }
+}
+
+/// A Channel Pressure (channel-wide aftertouch) message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelAftertouch {
+    pub channel:  u8,
+    pub pressure: u8
+}
+
+impl ChannelAftertouch {
+    pub fn to_bytes(&self) -> [u8; 2] {
+        [status(CHANNEL_AFTERTOUCH_PREFIX, self.channel), self.pressure]
+    }
+}
+
+/// A Pitch Bend message. `value` is 14-bit, with `PITCH_BEND_CENTER` meaning no bend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PitchBend {
+    pub channel: u8,
+    pub value:   u16
+}
+
+impl PitchBend {
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let (lsb, msb) = split14(self.value);
+        [status(PITCH_BEND_PREFIX, self.channel), lsb, msb]
+    }
+}
+
+/// A 14-bit controller value, sent as a pair of Control Changes.
+///
+/// `controller` is the MSB controller (0-31); its LSB partner is `controller + 32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlChange14 {
+    pub channel:    u8,
+    pub controller: u8,
+    pub value:      u16
+}
+
+impl ControlChange14 {
+    /// Expand into the MSB Control Change followed by the LSB one. Receivers reset the LSB when
+    /// the MSB arrives, so this order is required.
+    pub fn to_control_changes(&self) -> [ControlChange; 2] {
+        let (lsb, msb) = split14(self.value);
+        [
+            ControlChange::new(self.channel, self.controller, msb),
+            ControlChange::new(self.channel, self.controller + cc::LSB_OFFSET, lsb)
+        ]
+    }
+}
+
+/// Which parameter number space a parameter change addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterKind {
+    /// Registered Parameter Number (RPN), e.g. 0,0 for pitch bend range.
+    Registered,
+    /// Non-Registered Parameter Number (NRPN), defined by each manufacturer.
+    NonRegistered
+}
+
+/// The value written to a parameter through the Data Entry controllers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataEntry {
+    /// Data Entry MSB only, for parameters with 7-bit values.
+    Coarse(u8),
+    /// A full 14-bit value, sent as Data Entry MSB then LSB.
+    Fine(u16)
+}
+
+/// An RPN or NRPN change, which is sent as a sequence of Control Changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterChange {
+    pub channel: u8,
+    pub kind:    ParameterKind,
+    /// Parameter number MSB and LSB.
+    pub msb:     u8,
+    pub lsb:     u8,
+    pub value:   DataEntry
+}
+
+impl ParameterChange {
+    /// Expand into the Control Changes to send, in order: parameter number MSB and LSB
+    /// (101/100 for RPN, 99/98 for NRPN), then Data Entry MSB (6) and, for 14-bit values, LSB
+    /// (38).
+    ///
+    /// If `null` is set, the RPN null parameter (127,127) is selected afterwards so that later
+    /// stray Data Entry messages don't change the parameter.
+    pub fn to_control_changes(&self, null: bool) -> Vec<ControlChange> {
+        let (msb_cc, lsb_cc) = match self.kind {
+            ParameterKind::Registered    => (cc::RPN_MSB, cc::RPN_LSB),
+            ParameterKind::NonRegistered => (cc::NRPN_MSB, cc::NRPN_LSB)
+        };
+        let change = |controller, value| ControlChange::new(self.channel, controller, value);
+
+        let mut messages = vec![change(msb_cc, self.msb), change(lsb_cc, self.lsb)];
+
+        match self.value {
+            DataEntry::Coarse(value) => messages.push(change(cc::DATA_ENTRY_MSB, value)),
+            DataEntry::Fine(value)   => {
+                let (lsb, msb) = split14(value);
+                messages.push(change(cc::DATA_ENTRY_MSB, msb));
+                messages.push(change(cc::DATA_ENTRY_LSB, lsb));
+            }
+        }
+
+        if null {
+            messages.push(change(cc::RPN_MSB, 127));
+            messages.push(change(cc::RPN_LSB, 127));
+        }
+
+        messages
+    }
+}
+
+/// Any MIDI channel voice message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    NoteOff(NoteOff),
+    NoteOn(NoteOn),
+    PolyAftertouch(PolyAftertouch),
+    ControlChange(ControlChange),
+    ProgramChange(ProgramChange),
+    ChannelAftertouch(ChannelAftertouch),
+    PitchBend(PitchBend)
+}
+
+impl Message {
+    /// The zero-based channel the message is addressed to.
+    pub fn channel(&self) -> u8 {
+        match *self {
+            Message::NoteOff(m)           => m.channel,
+            Message::NoteOn(m)            => m.channel,
+            Message::PolyAftertouch(m)    => m.channel,
+            Message::ControlChange(m)     => m.channel,
+            Message::ProgramChange(m)     => m.channel,
+            Message::ChannelAftertouch(m) => m.channel,
+            Message::PitchBend(m)         => m.channel
+        }
+    }
+
+    /// Returns a copy of this message addressed to a different (zero-based) channel.
+    pub fn on_channel(self, channel: u8) -> Message {
+        match self {
+            Message::NoteOff(m)           => Message::NoteOff(NoteOff { channel, ..m }),
+            Message::NoteOn(m)            => Message::NoteOn(NoteOn { channel, ..m }),
+            Message::PolyAftertouch(m)    => Message::PolyAftertouch(PolyAftertouch { channel, ..m }),
+            Message::ControlChange(m)     => Message::ControlChange(m.on_channel(channel)),
+            Message::ProgramChange(m)     => Message::ProgramChange(ProgramChange { channel, ..m }),
+            Message::ChannelAftertouch(m) => Message::ChannelAftertouch(ChannelAftertouch { channel, ..m }),
+            Message::PitchBend(m)         => Message::PitchBend(PitchBend { channel, ..m })
+        }
+    }
+
+    /// Encode the message as the raw bytes to send to a port.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            Message::NoteOff(m)           => m.to_bytes().to_vec(),
+            Message::NoteOn(m)            => m.to_bytes().to_vec(),
+            Message::PolyAftertouch(m)    => m.to_bytes().to_vec(),
+            Message::ControlChange(m)     => m.to_bytes().to_vec(),
+            Message::ProgramChange(m)     => m.to_bytes().to_vec(),
+            Message::ChannelAftertouch(m) => m.to_bytes().to_vec(),
+            Message::PitchBend(m)         => m.to_bytes().to_vec()
+        }
+    }
+
+    /// Decode a complete channel voice message from raw bytes, such as those received from an
+    /// input port.
+    ///
+    /// Returns `None` for anything else: system messages, running status, or malformed data.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Message> {
+        let (&status, data) = bytes.split_first()?;
+        if !(NOTE_OFF_PREFIX..0xF0).contains(&status) || data.iter().any(|&b| b > 0x7F) {
+            return None;
+        }
+
+        let channel = status & 0x0F;
+        let message = match (status & 0xF0, data) {
+            (NOTE_OFF_PREFIX, &[note, velocity]) =>
+                Message::NoteOff(NoteOff { channel, note, velocity }),
+            (NOTE_ON_PREFIX, &[note, velocity]) =>
+                Message::NoteOn(NoteOn { channel, note, velocity }),
+            (POLY_AFTERTOUCH_PREFIX, &[note, pressure]) =>
+                Message::PolyAftertouch(PolyAftertouch { channel, note, pressure }),
+            (CONTROL_CHANGE_PREFIX, &[controller, value]) =>
+                Message::ControlChange(ControlChange { channel, controller, value }),
+            (PROGRAM_CHANGE_PREFIX, &[program]) =>
+                Message::ProgramChange(ProgramChange { channel, program }),
+            (CHANNEL_AFTERTOUCH_PREFIX, &[pressure]) =>
+                Message::ChannelAftertouch(ChannelAftertouch { channel, pressure }),
+            (PITCH_BEND_PREFIX, &[lsb, msb]) =>
+                Message::PitchBend(PitchBend { channel, value: u16::from(lsb) | u16::from(msb) << 7 }),
+            _ => return None
+        };
+        Some(message)
+    }
+
+    /// Describe the message like `Display`, naming its controller if it has a name.
+    pub fn describe(&self, names: &ControllerNames) -> String {
+        match *self {
+            Message::ControlChange(m) => match names.name_of(m.controller) {
+                Some(name) => format!("CC#{} ({}) value {}", m.controller, name, m.value),
+                None       => self.to_string()
+            },
+            _ => self.to_string()
+        }
+    }
+}
+
+impl From<ControlChange> for Message {
+    fn from(m: ControlChange) -> Message {
+        Message::ControlChange(m)
+    }
+}
+
+// describes the message without its channel, which callers usually print separately
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Message::NoteOff(m) =>
+                write!(f, "Note Off {} velocity {}", m.note, m.velocity),
+            Message::NoteOn(m) =>
+                write!(f, "Note On {} velocity {}", m.note, m.velocity),
+            Message::PolyAftertouch(m) =>
+                write!(f, "Poly Aftertouch note {} pressure {}", m.note, m.pressure),
+            Message::ControlChange(m) =>
+                write!(f, "CC#{} value {}", m.controller, m.value),
+            Message::ProgramChange(m) =>
+                write!(f, "Program Change {}", m.program),
+            Message::ChannelAftertouch(m) =>
+                write!(f, "Channel Aftertouch {}", m.pressure),
+            Message::PitchBend(m) =>
+                write!(f, "Pitch Bend {}", m.value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_status_byte_with_channel() {
+        assert_eq!(ControlChange::new(0, 122, 0).to_bytes(),    [0xB0, 122, 0]);
+        assert_eq!(ControlChange::new(2, 122, 127).to_bytes(),  [0xB2, 122, 127]);
+        assert_eq!(ControlChange::new(15, 74, 64).to_bytes(),   [0xBF, 74, 64]);
+    }
+
+    #[test]
+    fn on_channel_keeps_data() {
+        let cc = ControlChange::new(0, 70, 104).on_channel(9);
+        assert_eq!(cc, ControlChange::new(9, 70, 104));
+
+        let bend = Message::PitchBend(PitchBend { channel: 0, value: 100 }).on_channel(3);
+        assert_eq!(bend, Message::PitchBend(PitchBend { channel: 3, value: 100 }));
+        assert_eq!(bend.channel(), 3);
+    }
+
+    #[test]
+    fn encodes_every_voice_message() {
+        let cases = vec![
+            (Message::NoteOff(NoteOff { channel: 1, note: 60, velocity: 0 }),          vec![0x81, 60, 0]),
+            (Message::NoteOn(NoteOn { channel: 1, note: 60, velocity: 100 }),          vec![0x91, 60, 100]),
+            (Message::PolyAftertouch(PolyAftertouch { channel: 2, note: 64, pressure: 5 }), vec![0xA2, 64, 5]),
+            (Message::ControlChange(ControlChange::new(3, 7, 90)),                     vec![0xB3, 7, 90]),
+            (Message::ProgramChange(ProgramChange { channel: 4, program: 5 }),         vec![0xC4, 5]),
+            (Message::ChannelAftertouch(ChannelAftertouch { channel: 5, pressure: 9 }), vec![0xD5, 9]),
+            (Message::PitchBend(PitchBend { channel: 6, value: 0 }),                   vec![0xE6, 0, 0]),
+        ];
+
+        for (message, bytes) in cases {
+            assert_eq!(message.to_bytes(), bytes, "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let messages = vec![
+            Message::NoteOff(NoteOff { channel: 1, note: 60, velocity: 0 }),
+            Message::NoteOn(NoteOn { channel: 15, note: 127, velocity: 100 }),
+            Message::PolyAftertouch(PolyAftertouch { channel: 2, note: 64, pressure: 5 }),
+            Message::ControlChange(ControlChange::new(3, 7, 90)),
+            Message::ProgramChange(ProgramChange { channel: 4, program: 5 }),
+            Message::ChannelAftertouch(ChannelAftertouch { channel: 5, pressure: 9 }),
+            Message::PitchBend(PitchBend { channel: 6, value: 12000 }),
+        ];
+
+        for message in messages {
+            assert_eq!(Message::from_bytes(&message.to_bytes()), Some(message));
+        }
+    }
+
+    #[test]
+    fn ignores_other_bytes() {
+        assert_eq!(Message::from_bytes(&[]),                 None);
+        assert_eq!(Message::from_bytes(&[0xF8]),             None);
+        assert_eq!(Message::from_bytes(&[0xF0, 0x41, 0xF7]), None);
+        assert_eq!(Message::from_bytes(&[0x40, 0x40]),       None);
+        assert_eq!(Message::from_bytes(&[0xB0, 7]),          None);
+        assert_eq!(Message::from_bytes(&[0xB0, 7, 0x80]),    None);
+    }
+
+    #[test]
+    fn expands_14_bit_controller_msb_first() {
+        let pair = ControlChange14 { channel: 0, controller: 1, value: 12000 }.to_control_changes();
+        assert_eq!(pair, [ControlChange::new(0, 1, 93), ControlChange::new(0, 33, 96)]);
+    }
+
+    #[test]
+    fn expands_rpn_and_nrpn() {
+        let change = |kind, value| ParameterChange { channel: 1, kind, msb: 0, lsb: 2, value };
+        let bytes  = |ccs: Vec<ControlChange>| ccs.iter().map(|m| m.to_bytes().to_vec()).collect::<Vec<_>>();
+
+        assert_eq!(bytes(change(ParameterKind::Registered, DataEntry::Coarse(2)).to_control_changes(false)),
+                   vec![vec![0xB1, 101, 0], vec![0xB1, 100, 2], vec![0xB1, 6, 2]]);
+
+        assert_eq!(bytes(change(ParameterKind::NonRegistered, DataEntry::Fine(8193)).to_control_changes(true)),
+                   vec![vec![0xB1, 99, 0], vec![0xB1, 98, 2], vec![0xB1, 6, 64], vec![0xB1, 38, 1],
+                        vec![0xB1, 101, 127], vec![0xB1, 100, 127]]);
+    }
+
+    #[test]
+    fn describes_controller_names() {
+        let names = ControllerNames::standard();
+        assert_eq!(Message::from(ControlChange::new(0, 7, 100)).describe(&names), "CC#7 (volume) value 100");
+        assert_eq!(Message::from(ControlChange::new(0, 3, 1)).describe(&names),   "CC#3 value 1");
+    }
+
+    #[test]
+    fn pitch_bend_is_lsb_first() {
+        let bend = |value| PitchBend { channel: 0, value }.to_bytes();
+        assert_eq!(bend(PITCH_BEND_CENTER), [0xE0, 0x00, 0x40]);
+        assert_eq!(bend(VALUE14_MAX),       [0xE0, 0x7F, 0x7F]);
+        assert_eq!(bend(0x0081),            [0xE0, 0x01, 0x01]);
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use crate::channels::Channels;
+use crate::message::Message;
+use crate::names::ControllerNames;
+
+/// The kinds of message the monitor can be told to show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Note On and Note Off.
+    Note,
+    PolyAftertouch,
+    ControlChange,
+    ProgramChange,
+    ChannelAftertouch,
+    PitchBend,
+    SysEx,
+    /// Everything else: clock, start and stop, active sensing and so on.
+    System
+}
+
+impl Kind {
+    /// Look up a kind by the keyword used for it in the data (`note`, `poly`, `cc`, `pc`, `at`,
+    /// `bend`), or `sysex` or `system`.
+    pub fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "note" | "on" | "off" => Some(Kind::Note),
+            "poly"                => Some(Kind::PolyAftertouch),
+            "cc"                  => Some(Kind::ControlChange),
+            "pc" | "program"      => Some(Kind::ProgramChange),
+            "at" | "aftertouch"   => Some(Kind::ChannelAftertouch),
+            "bend"                => Some(Kind::PitchBend),
+            "sysex"               => Some(Kind::SysEx),
+            "system"              => Some(Kind::System),
+            _                     => None
+        }
+    }
+
+    /// The kind of a raw message.
+    pub fn of(bytes: &[u8]) -> Kind {
+        match Message::from_bytes(bytes) {
+            Some(Message::NoteOff(_)) | Some(Message::NoteOn(_)) => Kind::Note,
+            Some(Message::PolyAftertouch(_))     => Kind::PolyAftertouch,
+            Some(Message::ControlChange(_))      => Kind::ControlChange,
+            Some(Message::ProgramChange(_))      => Kind::ProgramChange,
+            Some(Message::ChannelAftertouch(_))  => Kind::ChannelAftertouch,
+            Some(Message::PitchBend(_))          => Kind::PitchBend,
+            None if bytes.first() == Some(&0xF0) => Kind::SysEx,
+            None                                 => Kind::System
+        }
+    }
+}
+
+/// Decides which received messages to show.
+///
+/// With no kinds given, everything but system messages is shown, since clock and active sensing
+/// would drown out the rest. Channel messages are only shown on the selected channels.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub kinds:    Vec<Kind>,
+    pub channels: Channels
+}
+
+impl Filter {
+    pub fn new(kinds: Vec<Kind>, channels: Channels) -> Filter {
+        Filter { kinds, channels }
+    }
+
+    pub fn accepts(&self, bytes: &[u8]) -> bool {
+        let kind = Kind::of(bytes);
+        let kind_shown = if self.kinds.is_empty() {
+            kind != Kind::System
+        }
+        else {
+            self.kinds.contains(&kind)
+        };
+
+        kind_shown && Message::from_bytes(bytes)
+            .is_none_or(|message| self.channels.contains(message.channel()))
+    }
+}
+
+/// Describe a received message on one line: when it arrived, the port it came from, and what it
+/// is, naming controllers from `names`.
+pub fn describe(elapsed: Duration, port: &str, bytes: &[u8], names: &ControllerNames) -> String {
+    let what = match Message::from_bytes(bytes) {
+        Some(message) => format!("ch#{:<2} {}", message.channel() + 1, message.describe(names)),
+        None          => {
+            let hex = bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+            match Kind::of(bytes) {
+                Kind::SysEx => format!("SysEx {}", hex),
+                _           => format!("System {}", hex)
+            }
+        }
+    };
+
+    format!("{:>10.3}  {}  {}", elapsed.as_secs_f64(), port, what)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_by_kind_and_channel() {
+        let default = Filter::new(Vec::new(), Channels::all());
+        assert!(default.accepts(&[0xB0, 7, 100]));
+        assert!(default.accepts(&[0xF0, 0x41, 0xF7]));
+        assert!(!default.accepts(&[0xF8]));
+
+        let cc_on_2 = Filter::new(vec![Kind::ControlChange, Kind::System], Channels::only(1));
+        assert!(cc_on_2.accepts(&[0xB1, 7, 100]));
+        assert!(!cc_on_2.accepts(&[0xB0, 7, 100]));
+        assert!(!cc_on_2.accepts(&[0x91, 60, 100]));
+        assert!(cc_on_2.accepts(&[0xF8]));
+    }
+
+    #[test]
+    fn describes_messages() {
+        let names = ControllerNames::standard();
+        let at    = Duration::from_millis(1500);
+
+        assert_eq!(describe(at, "JUNO", &[0xB0, 7, 100], &names),
+                   "     1.500  JUNO  ch#1  CC#7 (volume) value 100");
+        assert_eq!(describe(at, "JUNO", &[0x9F, 60, 1], &names),
+                   "     1.500  JUNO  ch#16 Note On 60 velocity 1");
+        assert_eq!(describe(at, "JUNO", &[0xF0, 0x41, 0xF7], &names),
+                   "     1.500  JUNO  SysEx F0 41 F7");
+        assert_eq!(describe(at, "JUNO", &[0xF8], &names),
+                   "     1.500  JUNO  System F8");
+    }
+}
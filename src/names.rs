@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::parse::{COMMENT, DATA_MAX, KEYWORDS};
+
+/// Standard controller names from the MIDI 1.0 specification.
+///
+/// Where a controller has more than one name, the first is the one shown when describing
+/// messages.
+pub const STANDARD: &[(&str, u8)] = &[
+    ("bankselect",          0),
+    ("bank",                0),
+    ("modwheel",            1),
+    ("modulation",          1),
+    ("breath",              2),
+    ("foot",                4),
+    ("portamentotime",      5),
+    ("dataentry",           6),
+    ("volume",              7),
+    ("balance",             8),
+    ("pan",                 10),
+    ("expression",          11),
+    ("effect1",             12),
+    ("effect2",             13),
+    ("general1",            16),
+    ("general2",            17),
+    ("general3",            18),
+    ("general4",            19),
+    ("bankselectlsb",       32),
+    ("modwheellsb",         33),
+    ("dataentrylsb",        38),
+    ("volumelsb",           39),
+    ("sustain",             64),
+    ("damper",              64),
+    ("portamento",          65),
+    ("sostenuto",           66),
+    ("soft",                67),
+    ("legato",              68),
+    ("hold2",               69),
+    ("variation",           70),
+    ("resonance",           71),
+    ("timbre",              71),
+    ("release",             72),
+    ("attack",              73),
+    ("cutoff",              74),
+    ("brightness",          74),
+    ("decay",               75),
+    ("vibratorate",         76),
+    ("vibratodepth",        77),
+    ("vibratodelay",        78),
+    ("portamentocontrol",   84),
+    ("reverb",              91),
+    ("tremolo",             92),
+    ("chorus",              93),
+    ("detune",              94),
+    ("phaser",              95),
+    ("dataincrement",       96),
+    ("datadecrement",       97),
+    ("nrpnlsb",             98),
+    ("nrpnmsb",             99),
+    ("rpnlsb",              100),
+    ("rpnmsb",              101),
+    ("allsoundoff",         120),
+    ("resetallcontrollers", 121),
+    ("local",               122),
+    ("allnotesoff",         123),
+    ("omnioff",             124),
+    ("omnion",              125),
+    ("monomode",            126),
+    ("polymode",            127)
+];
+
+/// Names for controller numbers: the standard ones, plus user-defined aliases.
+///
+/// Names are matched ignoring case, `-` and `_`, so `allnotesoff`, `all-notes-off` and
+/// `All_Notes_Off` are the same name. Aliases take precedence over standard names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControllerNames {
+    aliases: Vec<(String, u8)>
+}
+
+impl ControllerNames {
+    /// Only the standard names.
+    pub fn standard() -> ControllerNames {
+        ControllerNames::default()
+    }
+
+    /// Add an alias for a controller, replacing any existing alias with the same name.
+    ///
+    /// Returns `None` if the alias would never be recognised: the controller is above 127, or the
+    /// name is empty, all digits, a message keyword such as `pc`, or contains a separator, `:`,
+    /// `=`, `/` or the comment character `#`.
+    pub fn alias(mut self, name: &str, controller: u8) -> Option<ControllerNames> {
+        if !is_valid_name(name) || u32::from(controller) > DATA_MAX {
+            return None;
+        }
+
+        let key = normalize(name);
+        self.aliases.retain(|(alias, _)| normalize(alias) != key);
+        self.aliases.push((name.to_string(), controller));
+        Some(self)
+    }
+
+    /// Look up the controller number for a name.
+    pub fn lookup(&self, name: &str) -> Option<u8> {
+        let key = normalize(name);
+
+        self.aliases.iter()
+            .map(|(alias, controller)| (alias.as_str(), *controller))
+            .chain(STANDARD.iter().cloned())
+            .find(|&(candidate, _)| normalize(candidate) == key)
+            .map(|(_, controller)| controller)
+    }
+
+    /// The name to show for a controller number, if it has one.
+    pub fn name_of(&self, controller: u8) -> Option<&str> {
+        self.aliases.iter()
+            .map(|(alias, controller)| (alias.as_str(), *controller))
+            .chain(STANDARD.iter().cloned())
+            .find(|&(_, candidate)| candidate == controller)
+            .map(|(name, _)| name)
+    }
+
+    /// Every recognised name, aliases first.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.aliases.iter().map(|(alias, _)| alias.as_str())
+            .chain(STANDARD.iter().map(|&(name, _)| name))
+    }
+}
+
+/// Read a controller map file, adding its names to `names` as aliases.
+///
+/// A `.csv` file is read in the midi.guide layout: the `parameter_name` and `cc_msb` columns give
+/// each name and controller, rows without a controller are skipped, and names are lowercased with
+/// runs of other characters replaced by `-`, so `Filter Cutoff` becomes `filter-cutoff`. Any other
+/// file is read as a TOML table of names to controller numbers, like the config file's `[alias]`.
+pub fn read_map<P: AsRef<Path>>(path: P, names: ControllerNames) -> Result<ControllerNames, Error> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|error| Error::Io { path: path.to_path_buf(), error })?;
+    let invalid = |line, reason: String| Error::Map { path: path.to_path_buf(), line, reason };
+
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv")) {
+        return read_csv_map(&text, names).map_err(|(line, reason)| invalid(line, reason));
+    }
+
+    let map: BTreeMap<String, u8> = toml::from_str(&text)
+        .map_err(|error| Error::Config { path: path.to_path_buf(), error })?;
+    map.iter().try_fold(names, |names, (name, &controller)| {
+        names.alias(name, controller)
+            .ok_or_else(|| invalid(0, format!("invalid name {} = {}", name, controller)))
+    })
+}
+
+// add the names from a midi.guide CSV file, reporting errors with their line number
+fn read_csv_map(text: &str, mut names: ControllerNames)
+    -> Result<ControllerNames, (usize, String)>
+{
+    let mut records = csv_records(text);
+    let (_, header) = records.next().ok_or((1, "missing header".to_string()))??;
+
+    let column = |name: &str| header.iter().position(|field| field.trim() == name)
+        .ok_or_else(|| (1, format!("missing {} column", name)));
+    let (name_column, cc_column) = (column("parameter_name")?, column("cc_msb")?);
+
+    for record in records {
+        let (line, fields) = record?;
+        let cc = fields.get(cc_column).map_or("", |cc| cc.trim());
+        if cc.is_empty() {
+            continue;
+        }
+
+        let controller = cc.parse::<u8>().ok().filter(|&cc| u32::from(cc) <= DATA_MAX)
+            .ok_or_else(|| (line, format!("invalid cc_msb {}", cc)))?;
+        let name = fields.get(name_column).map_or(String::new(), |name| slug(name));
+
+        // names which can't be written in data, such as ones made only of digits, are skipped
+        names = names.clone().alias(&name, controller).unwrap_or(names);
+    }
+
+    Ok(names)
+}
+
+// split CSV text into records of fields, each with the line number it starts on
+fn csv_records(text: &str)
+    -> impl Iterator<Item = Result<(usize, Vec<String>), (usize, String)>> + '_
+{
+    let mut chars = text.chars().peekable();
+    let mut line  = 1;
+
+    std::iter::from_fn(move || {
+        // skip blank lines between records
+        while chars.peek().is_some_and(|&c| c == '\n' || c == '\r') {
+            line += usize::from(chars.next() == Some('\n'));
+        }
+        chars.peek()?;
+
+        let start  = line;
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"'                 => quoted = !quoted,
+                ',' if !quoted      => fields.push(String::new()),
+                '\r' if !quoted     => (),
+                '\n' if !quoted     => {
+                    line += 1;
+                    break;
+                }
+                c => {
+                    line += usize::from(c == '\n');
+                    fields.last_mut().unwrap().push(c);
+                }
+            }
+        }
+
+        if quoted {
+            return Some(Err((start, "unterminated quoted field".to_string())));
+        }
+        Some(Ok((start, fields)))
+    })
+}
+
+// turn a parameter name into one which can be written in data
+fn slug(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// whether a name could appear as the first field of a data token
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.bytes().all(|b| b.is_ascii_digit())
+        && !name.contains(|c: char| c.is_whitespace() || c == COMMENT || ",;:=/".contains(c))
+        && !KEYWORDS.contains(&normalize(name).as_str())
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|&c| c != '-' && c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_standard_names() {
+        let names = ControllerNames::standard();
+        assert_eq!(names.lookup("modwheel"),      Some(1));
+        assert_eq!(names.lookup("Volume"),        Some(7));
+        assert_eq!(names.lookup("all-notes-off"), Some(123));
+        assert_eq!(names.lookup("local"),         Some(122));
+        assert_eq!(names.lookup("damper"),        Some(64));
+        assert_eq!(names.lookup("nonsense"),      None);
+
+        assert_eq!(names.name_of(64), Some("sustain"));
+        assert_eq!(names.name_of(3),  None);
+    }
+
+    #[test]
+    fn aliases_take_precedence() {
+        let names = ControllerNames::standard()
+            .alias("filter", 74).unwrap()
+            .alias("cutoff", 16).unwrap();
+
+        assert_eq!(names.lookup("FILTER"), Some(74));
+        assert_eq!(names.lookup("cutoff"), Some(16));
+        assert_eq!(names.name_of(74),      Some("filter"));
+        assert_eq!(names.names().next(),   Some("filter"));
+    }
+
+    #[test]
+    fn reads_midi_guide_csv() {
+        let csv = "manufacturer,device,section,parameter_name,parameter_description,cc_msb,cc_lsb\n\
+                   Roland,JUNO-DS,Filter,Filter Cutoff,\"Brightness, \"\"in\"\" Hz\",74,\n\
+                   Roland,JUNO-DS,Filter,Resonance,\"Two\nlines\",71,\n\
+                   Roland,JUNO-DS,System,Tone Select,,,\n";
+        let names = read_csv_map(csv, ControllerNames::standard()).unwrap();
+        assert_eq!(names.lookup("filter-cutoff"), Some(74));
+        assert_eq!(names.lookup("resonance"),     Some(71));
+        assert_eq!(names.lookup("tone-select"),   None);
+
+        let bad = "parameter_name,cc_msb\nCutoff,74\nResonance,200\n";
+        assert_eq!(read_csv_map(bad, ControllerNames::standard()).unwrap_err().0, 3);
+        assert!(read_csv_map("name,cc\n", ControllerNames::standard()).is_err());
+    }
+
+    #[test]
+    fn rejects_unusable_aliases() {
+        let names = ControllerNames::standard();
+        assert_eq!(names.clone().alias("", 1),        None);
+        assert_eq!(names.clone().alias("74", 1),      None);
+        assert_eq!(names.clone().alias("a:b", 1),     None);
+        assert_eq!(names.clone().alias("a#b", 1),     None);
+        assert_eq!(names.clone().alias("#", 1),       None);
+        assert_eq!(names.clone().alias("PC", 1),      None);
+        assert_eq!(names.clone().alias("filter", 128), None);
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::{Mutex, PoisonError};
+
+use midir::{MidiOutputConnection, SendError};
+
+/// Something raw MIDI messages can be sent to.
+///
+/// The midir connection is the real backend; `Recorder` captures messages in memory instead, so
+/// what would be emitted can be checked without any MIDI device attached.
+pub trait OutputSink {
+    /// Send one complete raw MIDI message.
+    fn send(&mut self, message: &[u8]) -> Result<(), SendError>;
+}
+
+impl OutputSink for MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        MidiOutputConnection::send(self, message)
+    }
+}
+
+impl<S: OutputSink + ?Sized> OutputSink for Box<S> {
+    fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        (**self).send(message)
+    }
+}
+
+impl<S: OutputSink + ?Sized> OutputSink for &mut S {
+    fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        (**self).send(message)
+    }
+}
+
+// sending to several outputs sends to each in turn, so a message reaches every port before the
+// next one is sent
+impl<S: OutputSink> OutputSink for Vec<S> {
+    fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        // keep sending to the rest even if one fails, reporting the first failure
+        let mut result = Ok(());
+        for sink in self.iter_mut() {
+            result = result.and(sink.send(message));
+        }
+        result
+    }
+}
+
+// a shared output is locked for each message, so other threads can use it in between
+impl<S: OutputSink + ?Sized> OutputSink for &Mutex<S> {
+    fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        self.lock().unwrap_or_else(PoisonError::into_inner).send(message)
+    }
+}
+
+/// An in-memory backend which records every message sent to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recorder {
+    /// Every message sent so far, in order.
+    pub sent: Vec<Vec<u8>>
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    /// Forget everything recorded so far.
+    pub fn clear(&mut self) {
+        self.sent.clear();
+    }
+}
+
+impl OutputSink for Recorder {
+    fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        self.sent.push(message.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorder_keeps_messages_in_order() {
+        let mut recorder = Recorder::new();
+        recorder.send(&[0xB0, 122, 0]).unwrap();
+        recorder.send(&[0xB1, 122, 127]).unwrap();
+        assert_eq!(recorder.sent, vec![vec![0xB0, 122, 0], vec![0xB1, 122, 127]]);
+
+        recorder.clear();
+        assert!(recorder.sent.is_empty());
+    }
+
+    #[test]
+    fn vec_sink_sends_to_each() {
+        let mut sinks = vec![Recorder::new(), Recorder::new()];
+        sinks.send(&[0xC0, 5]).unwrap();
+        assert!(sinks.iter().all(|sink| sink.sent == vec![vec![0xC0, 5]]));
+    }
+
+    #[test]
+    fn boxed_sink_forwards() {
+        let mut recorder = Recorder::new();
+        {
+            let mut boxed: Box<dyn OutputSink + '_> = Box::new(&mut recorder);
+            boxed.send(&[0xC0, 5]).unwrap();
+        }
+        assert_eq!(recorder.sent, vec![vec![0xC0, 5]]);
+    }
+}
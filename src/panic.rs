@@ -0,0 +1,53 @@
+use crate::message::{cc, ControlChange, Message, NoteOff};
+use crate::parse::Program;
+
+/// Highest MIDI note number.
+pub const NOTE_MAX: u8 = 127;
+
+/// A program which silences stuck notes: All Sound Off, Reset All Controllers then All Notes Off.
+///
+/// Some devices ignore the channel mode messages, so with `brute_force` a Note Off is also sent
+/// for every key.
+pub fn program(brute_force: bool) -> Program {
+    let mut messages: Vec<Message> = vec![
+        ControlChange::new(0, cc::ALL_SOUND_OFF, 0).into(),
+        ControlChange::new(0, cc::RESET_ALL_CONTROLLERS, 0).into(),
+        ControlChange::new(0, cc::ALL_NOTES_OFF, 0).into(),
+    ];
+
+    if brute_force {
+        messages.extend((0..=NOTE_MAX).map(|note| {
+            Message::NoteOff(NoteOff { channel: 0, note, velocity: 0 })
+        }));
+    }
+
+    Program { events: messages.into_iter().map(Into::into).collect() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channels::Channels;
+    use crate::emitter::Emitter;
+    use crate::output::Recorder;
+    use crate::ports::PortSelector;
+
+    #[test]
+    fn sends_channel_mode_messages() {
+        let mut recorder = Recorder::new();
+        Emitter::new(PortSelector::all(), Channels::only(0))
+            .emit_to(&mut recorder, &program(false));
+        assert_eq!(recorder.sent, vec![vec![0xB0, 120, 0], vec![0xB0, 121, 0], vec![0xB0, 123, 0]]);
+    }
+
+    #[test]
+    fn brute_force_releases_every_key() {
+        let mut recorder = Recorder::new();
+        Emitter::new(PortSelector::all(), Channels::parse("1-2").unwrap())
+            .emit_to(&mut recorder, &program(true));
+
+        assert_eq!(recorder.sent.len(), 2 * (3 + 128));
+        assert_eq!(recorder.sent[6], vec![0x80, 0, 0]);
+        assert_eq!(recorder.sent.last().unwrap(), &vec![0x81, 127, 0]);
+    }
+}
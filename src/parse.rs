@@ -0,0 +1,881 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::channels::Channels;
+use crate::names::ControllerNames;
+use crate::ramp::{self, Curve, Ramp};
+use crate::message::{
+    cc, ChannelAftertouch, ControlChange, ControlChange14, DataEntry, Message, NoteOff, NoteOn,
+    ParameterChange, ParameterKind, PitchBend, PolyAftertouch, ProgramChange, VALUE14_MAX
+};
+
+/// Largest value allowed in a 7-bit MIDI data byte.
+pub const DATA_MAX: u32 = 127;
+
+/// The longest time a program may last, so every message's send time can be scheduled.
+pub const MAX_TIME: Duration = Duration::from_secs(u32::MAX as u64);
+
+/// Words which start a message token, and so can't be used as controller names.
+pub const KEYWORDS: &[&str] = &[
+    "cc", "cc14", "pc", "program", "note", "on", "off", "noteoff", "poly", "at", "aftertouch",
+    "bend", "rpn", "rpn14", "nrpn", "nrpn14", "wait"
+];
+
+/// A message in a program, together with the channels it should be sent on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// The message, stored on channel 0; the emitter readdresses it to each channel it's sent on.
+    pub message:  Message,
+    /// Channels given with a `ch<N>/` prefix, which replace the emitter's channel selection for
+    /// this message. `None` sends it on every selected channel.
+    pub channels: Option<Channels>,
+    /// When to send the message, relative to the start of the program.
+    pub at:       Duration
+}
+
+impl From<Message> for Event {
+    fn from(message: Message) -> Event {
+        Event { message, channels: None, at: Duration::from_secs(0) }
+    }
+}
+
+/// A parsed sequence of messages, in the order they should be sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub events: Vec<Event>
+}
+
+impl Program {
+    /// Parse MIDI CC data with the default `Parser`.
+    pub fn parse(data: &str) -> Result<Program, ParseError> {
+        Parser::new().parse(data)
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(data: &str) -> Result<Program, ParseError> {
+        Program::parse(data)
+    }
+}
+
+/// What was wrong with the data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A number was expected but something else was found.
+    InvalidNumber(String),
+    /// A number was outside the range allowed in its position.
+    OutOfRange { value: String, max: u32 },
+    /// A token didn't match any known form.
+    UnexpectedToken(String),
+    /// A message keyword was recognised but given the wrong number of fields.
+    Usage { token: String, usage: &'static str },
+    /// A controller was given by a name which isn't a standard name or alias.
+    UnknownController(String),
+    /// A duration wasn't a number followed by `s` or `ms`.
+    InvalidDuration(String),
+    /// A duration, or the time the program would reach by its end, was longer than `MAX_TIME`.
+    DurationOutOfRange(String),
+    /// A ramp curve wasn't `lin`, `exp` or `log`.
+    UnknownCurve(String)
+}
+
+/// An error in the data, located by the byte offset at which it occurred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset of the offending text from the start of the data.
+    pub offset: usize,
+    pub kind:   ParseErrorKind
+}
+
+impl ParseError {
+    pub fn new(offset: usize, kind: ParseErrorKind) -> ParseError {
+        ParseError { offset, kind }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::InvalidNumber(ref s) =>
+                write!(f, "'{}' at byte {} is not a number", s, self.offset),
+            ParseErrorKind::OutOfRange { ref value, max } =>
+                write!(f, "{} at byte {} is out of range 0-{}", value, self.offset, max),
+            ParseErrorKind::UnexpectedToken(ref s) =>
+                write!(f, "unexpected '{}' at byte {}", s, self.offset),
+            ParseErrorKind::Usage { ref token, usage } =>
+                write!(f, "'{}' at byte {} should look like {}", token, self.offset, usage),
+            ParseErrorKind::UnknownController(ref s) =>
+                write!(f, "unknown controller name '{}' at byte {}", s, self.offset),
+            ParseErrorKind::InvalidDuration(ref s) =>
+                write!(f, "'{}' at byte {} is not a duration such as 2s or 50ms", s, self.offset),
+            ParseErrorKind::DurationOutOfRange(ref s) =>
+                write!(f, "'{}' at byte {} runs past the longest time allowed, {}s", s, self.offset,
+                       MAX_TIME.as_secs()),
+            ParseErrorKind::UnknownCurve(ref s) =>
+                write!(f, "unknown curve '{}' at byte {}, expected lin, exp or log", s, self.offset)
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// A piece of text together with its byte offset in the original data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub offset: usize,
+    pub text:   &'a str
+}
+
+impl<'a> Token<'a> {
+    /// Split the token on `sep`, keeping track of where each field starts.
+    pub fn fields(self, sep: char) -> impl Iterator<Item = Token<'a>> {
+        let mut offset = self.offset;
+        self.text.split(sep).map(move |text| {
+            let field = Token { offset, text };
+            offset += text.len() + sep.len_utf8();
+            field
+        })
+    }
+
+    /// Parse the token as a decimal number no greater than `max`.
+    pub fn number(self, max: u32) -> Result<u32, ParseError> {
+        if self.text.is_empty() || !self.text.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(self.error(ParseErrorKind::InvalidNumber(self.text.to_string())));
+        }
+
+        // an all-digit string can only fail to parse by overflowing, which is out of range anyway
+        match self.text.parse::<u32>() {
+            Ok(n) if n <= max => Ok(n),
+            _ => Err(self.error(ParseErrorKind::OutOfRange { value: self.text.to_string(), max }))
+        }
+    }
+
+    /// Parse the token as a 7-bit MIDI data byte.
+    pub fn data_byte(self) -> Result<u8, ParseError> {
+        self.number(DATA_MAX).map(|n| n as u8)
+    }
+
+    /// Parse the token as a 14-bit MIDI value.
+    pub fn value14(self) -> Result<u16, ParseError> {
+        self.number(u32::from(VALUE14_MAX)).map(|n| n as u16)
+    }
+
+    /// Parse the token as a duration: a decimal number of seconds (`2s`, `0.5s`) or
+    /// milliseconds (`50ms`), no longer than `MAX_TIME`.
+    pub fn duration(self) -> Result<Duration, ParseError> {
+        let invalid = || self.error(ParseErrorKind::InvalidDuration(self.text.to_string()));
+
+        let (number, scale) = if let Some(number) = self.text.strip_suffix("ms") {
+            (number, 0.001)
+        }
+        else if let Some(number) = self.text.strip_suffix('s') {
+            (number, 1.0)
+        }
+        else {
+            return Err(invalid());
+        };
+
+        // only plain decimals, so nothing like "inf" or "1e9" sneaks through
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+            return Err(invalid());
+        }
+        let seconds = number.parse::<f64>().map_err(|_| invalid())? * scale;
+        if seconds > MAX_TIME.as_secs_f64() {
+            return Err(self.error(ParseErrorKind::DurationOutOfRange(self.text.to_string())));
+        }
+        Ok(Duration::from_secs_f64(seconds))
+    }
+
+    /// Split the token in two at a byte index.
+    pub fn split_at(self, mid: usize) -> (Token<'a>, Token<'a>) {
+        let (left, right) = self.text.split_at(mid);
+        (Token { offset: self.offset, text: left }, Token { offset: self.offset + mid, text: right })
+    }
+
+    pub fn error(self, kind: ParseErrorKind) -> ParseError {
+        ParseError::new(self.offset, kind)
+    }
+
+    pub fn unexpected(self) -> ParseError {
+        self.error(ParseErrorKind::UnexpectedToken(self.text.to_string()))
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || c == ',' || c == ';'
+}
+
+// whether a comma at the start of `rest` continues a token rather than separating two, as in
+// `rpn:0,0=2` where it sits between the parameter number bytes
+fn continues_token(rest: &str) -> bool {
+    match rest.strip_prefix(',') {
+        Some(after) => {
+            let digits = after.find(|c: char| !c.is_ascii_digit()).unwrap_or(after.len());
+            digits > 0 && after[digits..].starts_with('=')
+        }
+        None => false
+    }
+}
+
+// starts a comment running to the end of the line
+pub(crate) const COMMENT: char = '#';
+
+/// Split data into tokens separated by whitespace, commas or semicolons.
+///
+/// A comma followed by a number and `=` does not separate tokens, so that `rpn:0,0=2` stays whole.
+/// A `#` starts a comment, which runs to the end of the line.
+pub fn tokens(data: &str) -> impl Iterator<Item = Token<'_>> {
+    let mut rest   = data;
+    let mut offset = 0;
+
+    std::iter::from_fn(move || {
+        // skip leading separators and comments
+        let mut start = rest.find(|c| !is_separator(c))?;
+        while rest[start..].starts_with(COMMENT) {
+            let end = rest[start..].find('\n').map_or(rest.len(), |n| start + n);
+            rest    = &rest[end..];
+            offset += end;
+            start   = rest.find(|c| !is_separator(c))?;
+        }
+
+        let mut end = start;
+        loop {
+            end += rest[end..].find(|c| is_separator(c) || c == COMMENT)
+                .unwrap_or(rest.len() - end);
+            if end < rest.len() && continues_token(&rest[end..]) {
+                end += 1;
+            }
+            else {
+                break;
+            }
+        }
+
+        let token = Token { offset: offset + start, text: &rest[start..end] };
+        rest    = &rest[end..];
+        offset += end;
+        Some(token)
+    })
+}
+
+/// Parses the data syntax into a `Program`.
+///
+/// The data is a list of tokens separated by whitespace, commas or semicolons. Each token is one
+/// channel voice message, written as fields joined by `:`:
+///
+/// | Token                         | Message                              |
+/// |-------------------------------|--------------------------------------|
+/// | `<cc>:<value>`                | Control Change (`<cc>` may be a name) |
+/// | `cc:<cc>:<value>`             | Control Change                       |
+/// | `cc14:<cc>=<value>`           | 14-bit CC (0-16383): MSB on `<cc>` (0-31), LSB on `<cc>+32` |
+/// | `pc:<program>`                | Program Change                       |
+/// | `note:<note>:<velocity>`      | Note On (also `on:`)                 |
+/// | `off:<note>[:<velocity>]`     | Note Off (also `noteoff:`)           |
+/// | `poly:<note>:<pressure>`      | Polyphonic Aftertouch                |
+/// | `at:<pressure>`               | Channel Aftertouch (also `aftertouch:`) |
+/// | `bend:<value>`                | Pitch Bend, 0-16383 with 8192 centre |
+/// | `rpn:<msb>,<lsb>=<value>`     | RPN with 7-bit Data Entry            |
+/// | `rpn14:<msb>,<lsb>=<value>`   | RPN with 14-bit Data Entry (0-16383) |
+/// | `nrpn:<msb>,<lsb>=<value>`    | NRPN with 7-bit Data Entry           |
+/// | `nrpn14:<msb>,<lsb>=<value>`  | NRPN with 14-bit Data Entry (0-16383) |
+/// | `<cc>:<a>..<b>@<time>[:<curve>]` | Control Change ramp from `<a>` to `<b>` |
+/// | `wait:<time>`                 | Pause before the next message        |
+///
+/// Every other number is a decimal within the range [0-127], e.g. `70:104 pc:5,note:60:100`.
+///
+/// Controllers may be given by name wherever a controller number is accepted, e.g. `volume:100`
+/// or `cc14:modwheel=8192`. Names are the standard ones in `names::STANDARD` plus any aliases in
+/// `names`.
+///
+/// Any token may be prefixed with `ch<N>/` or `ch<A>-<B>/` to send it only on those (1-based)
+/// channels instead of the emitter's selection, e.g. `ch3/74:100`.
+///
+/// RPN and NRPN tokens expand to the Control Change sequence selecting the parameter and
+/// writing its value; with `rpn_null` set, the RPN null parameter is selected afterwards.
+///
+/// A ramp such as `74:0..127@2s` sweeps the controller over the given time (`2s`, `500ms`) at
+/// `ramp_rate` messages per second, with a `lin` (default), `exp` or `log` curve. Messages after
+/// a ramp are sent once it finishes, and `wait:<time>` delays the messages after it in the same
+/// way.
+///
+/// A `#` starts a comment running to the end of the line, so programs can be kept in commented,
+/// multi-line files. Anything else is an error; nothing is silently ignored.
+#[derive(Debug, Clone)]
+pub struct Parser {
+    /// Send the RPN null parameter after each RPN or NRPN change.
+    pub rpn_null:  bool,
+    /// Names accepted in place of controller numbers.
+    pub names:     ControllerNames,
+    /// Messages per second sent by ramps.
+    pub ramp_rate: u32
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser { rpn_null: false, names: ControllerNames::standard(), ramp_rate: ramp::DEFAULT_RATE }
+    }
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser::default()
+    }
+
+    pub fn rpn_null(mut self, rpn_null: bool) -> Parser {
+        self.rpn_null = rpn_null;
+        self
+    }
+
+    pub fn names(mut self, names: ControllerNames) -> Parser {
+        self.names = names;
+        self
+    }
+
+    pub fn ramp_rate(mut self, ramp_rate: u32) -> Parser {
+        self.ramp_rate = ramp_rate;
+        self
+    }
+
+    pub fn parse(&self, data: &str) -> Result<Program, ParseError> {
+        let mut events   = Vec::new();
+        let mut messages = Vec::new();
+        // when the next message is due, which ramps push back
+        let mut at       = Duration::from_secs(0);
+
+        // move the time on, as long as the program stays schedulable
+        let later = |at: Duration, by: Duration, token: Token| {
+            at.checked_add(by)
+                .filter(|&at| at <= MAX_TIME)
+                .ok_or_else(|| token.error(ParseErrorKind::DurationOutOfRange(token.text.to_string())))
+        };
+
+        for token in tokens(data) {
+            let (channels, token) = self.channel_prefix(token)?;
+
+            if let Some(duration) = self.wait(token)? {
+                if channels.is_some() {
+                    return Err(token.unexpected());
+                }
+                at = later(at, duration, token)?;
+                continue;
+            }
+
+            if let Some(ramp) = self.ramp(token)? {
+                // every step falls within the ramp, so checking its end covers them all
+                let end = later(at, ramp.duration, token)?;
+                events.extend(ramp.steps(self.ramp_rate).into_iter().map(|(offset, value)| Event {
+                    message: ControlChange::new(0, ramp.controller, value).into(), channels, at: at + offset
+                }));
+                at = end;
+                continue;
+            }
+
+            self.messages(token, &mut messages)?;
+            events.extend(messages.drain(..).map(|message| Event { message, channels, at }));
+        }
+
+        Ok(Program { events })
+    }
+
+    // split off a ch<N>/ prefix, if present
+    fn channel_prefix<'a>(&self, token: Token<'a>)
+        -> Result<(Option<Channels>, Token<'a>), ParseError>
+    {
+        let is_prefixed = token.text.starts_with("ch")
+            && token.text[2..].starts_with(|c: char| c.is_ascii_digit());
+
+        match token.text.find('/') {
+            Some(slash) if is_prefixed => {
+                let (prefix, rest) = token.split_at(slash);
+                let (_, spec)      = prefix.split_at(2);
+                let (_, rest)      = rest.split_at(1);
+                Ok((Some(Channels::parse_token(spec)?), rest))
+            }
+            _ => Ok((None, token))
+        }
+    }
+
+    // parse one token into the messages it stands for
+    fn messages(&self, token: Token, messages: &mut Vec<Message>) -> Result<(), ParseError> {
+        let keyword = token.fields(':').next().unwrap().text;
+
+        match keyword {
+            "cc14" => {
+                let pair = self.control_change14(token)?.to_control_changes();
+                messages.extend(pair.iter().cloned().map(Message::from));
+            }
+            "rpn" | "rpn14" | "nrpn" | "nrpn14" => {
+                let changes = self.parameter(token)?.to_control_changes(self.rpn_null);
+                messages.extend(changes.into_iter().map(Message::from));
+            }
+            _ => messages.push(self.message(token)?)
+        }
+
+        Ok(())
+    }
+
+    // wait:<duration>
+    fn wait(&self, token: Token) -> Result<Option<Duration>, ParseError> {
+        let fields: Vec<Token> = token.fields(':').collect();
+
+        match fields[..] {
+            [keyword, duration] if keyword.text == "wait" => Ok(Some(duration.duration()?)),
+            [keyword, ..] if keyword.text == "wait" => Err(token.error(ParseErrorKind::Usage {
+                token: token.text.to_string(), usage: "wait:<time>"
+            })),
+            _ => Ok(None)
+        }
+    }
+
+    // <cc>:<start>..<end>@<duration>[:<curve>], optionally prefixed with cc:
+    fn ramp(&self, token: Token) -> Result<Option<Ramp>, ParseError> {
+        if !token.text.contains("..") {
+            return Ok(None);
+        }
+
+        let usage = || token.error(ParseErrorKind::Usage {
+            token: token.text.to_string(), usage: "<cc>:<start>..<end>@<time>[:<curve>]"
+        });
+
+        let mut fields: Vec<Token> = token.fields(':').collect();
+        if fields[0].text == "cc" {
+            fields.remove(0);
+        }
+        let (controller, range, curve) = match fields[..] {
+            [controller, range]        => (controller, range, None),
+            [controller, range, curve] => (controller, range, Some(curve)),
+            _                          => return Err(usage())
+        };
+
+        let (values, duration) = match range.fields('@').collect::<Vec<_>>()[..] {
+            [values, duration] => (values, duration.duration()?),
+            _                  => return Err(usage())
+        };
+        let (start, end) = match values.text.find("..") {
+            Some(dots) => {
+                let (start, rest) = values.split_at(dots);
+                (start.data_byte()?, rest.split_at(2).1.data_byte()?)
+            }
+            None => return Err(usage())
+        };
+
+        let curve = match curve {
+            Some(curve) => Curve::from_name(curve.text)
+                .ok_or_else(|| curve.error(ParseErrorKind::UnknownCurve(curve.text.to_string())))?,
+            None => Curve::Linear
+        };
+
+        Ok(Some(Ramp { controller: self.controller(controller, DATA_MAX)?, start, end, duration, curve }))
+    }
+
+    // cc14:<cc>=<value>
+    fn control_change14(&self, token: Token) -> Result<ControlChange14, ParseError> {
+        let usage = || token.error(ParseErrorKind::Usage {
+            token: token.text.to_string(), usage: "cc14:<cc>=<value>"
+        });
+
+        let assignment = match token.fields(':').collect::<Vec<_>>()[..] {
+            [_, assignment] => assignment,
+            _               => return Err(usage())
+        };
+
+        match assignment.fields('=').collect::<Vec<_>>()[..] {
+            [controller, value] => Ok(ControlChange14 {
+                channel:    0,
+                controller: self.controller(controller, u32::from(cc::HIGH_RES_MAX))?,
+                value:      value.value14()?
+            }),
+            _ => Err(usage())
+        }
+    }
+
+    // rpn:<msb>,<lsb>=<value> and friends
+    fn parameter(&self, token: Token) -> Result<ParameterChange, ParseError> {
+        let mut fields    = token.fields(':');
+        let keyword       = fields.next().unwrap().text;
+        let (kind, usage) = match keyword {
+            "rpn" | "rpn14" => (ParameterKind::Registered,    "rpn:<msb>,<lsb>=<value>"),
+            _               => (ParameterKind::NonRegistered, "nrpn:<msb>,<lsb>=<value>")
+        };
+        let usage = || token.error(ParseErrorKind::Usage { token: token.text.to_string(), usage });
+
+        let assignment = match (fields.next(), fields.next()) {
+            (Some(assignment), None) => assignment,
+            _                        => return Err(usage())
+        };
+
+        let (number, value) = match assignment.fields('=').collect::<Vec<_>>()[..] {
+            [number, value] => (number, value),
+            _               => return Err(usage())
+        };
+
+        let (msb, lsb) = match number.fields(',').collect::<Vec<_>>()[..] {
+            [msb, lsb] => (msb.data_byte()?, lsb.data_byte()?),
+            _          => return Err(usage())
+        };
+
+        let value = if keyword.ends_with("14") {
+            DataEntry::Fine(value.value14()?)
+        }
+        else {
+            DataEntry::Coarse(value.data_byte()?)
+        };
+
+        Ok(ParameterChange { channel: 0, kind, msb, lsb, value })
+    }
+
+    /// Parse a controller given by number or name, as in the data.
+    pub fn parse_controller(&self, text: &str) -> Result<u8, ParseError> {
+        self.controller(Token { offset: 0, text }, DATA_MAX)
+    }
+
+    // a controller number, or a name for one, no greater than `max`
+    fn controller(&self, token: Token, max: u32) -> Result<u8, ParseError> {
+        if token.text.bytes().all(|b| b.is_ascii_digit()) {
+            return token.number(max).map(|n| n as u8);
+        }
+
+        match self.names.lookup(token.text) {
+            Some(controller) if u32::from(controller) <= max => Ok(controller),
+            Some(_) => Err(token.error(ParseErrorKind::OutOfRange { value: token.text.to_string(), max })),
+            None    => Err(token.error(ParseErrorKind::UnknownController(token.text.to_string())))
+        }
+    }
+
+    fn message(&self, token: Token) -> Result<Message, ParseError> {
+        let fields: Vec<Token> = token.fields(':').collect();
+        let keyword = fields[0].text;
+
+        // a bare number in first position is the original <CC>:<Value> form
+        if keyword.bytes().all(|b| b.is_ascii_digit()) {
+            return match fields[..] {
+                [cc, value] => Ok(ControlChange::new(0, cc.data_byte()?, value.data_byte()?).into()),
+                _           => Err(token.unexpected())
+            };
+        }
+
+        let usage = |usage| token.error(ParseErrorKind::Usage { token: token.text.to_string(), usage });
+        let args  = &fields[1..];
+
+        let channel = 0;
+        let message = match keyword {
+            "cc" => match *args {
+                [cc, value] => ControlChange::new(channel, self.controller(cc, DATA_MAX)?, value.data_byte()?).into(),
+                _           => return Err(usage("cc:<cc>:<value>"))
+            },
+            "pc" | "program" => match *args {
+                [program] => Message::ProgramChange(ProgramChange { channel, program: program.data_byte()? }),
+                _         => return Err(usage("pc:<program>"))
+            },
+            "note" | "on" => match *args {
+                [note, velocity] => Message::NoteOn(NoteOn {
+                    channel, note: note.data_byte()?, velocity: velocity.data_byte()?
+                }),
+                _ => return Err(usage("note:<note>:<velocity>"))
+            },
+            "off" | "noteoff" => match *args {
+                [note] => Message::NoteOff(NoteOff { channel, note: note.data_byte()?, velocity: 0 }),
+                [note, velocity] => Message::NoteOff(NoteOff {
+                    channel, note: note.data_byte()?, velocity: velocity.data_byte()?
+                }),
+                _ => return Err(usage("off:<note>[:<velocity>]"))
+            },
+            "poly" => match *args {
+                [note, pressure] => Message::PolyAftertouch(PolyAftertouch {
+                    channel, note: note.data_byte()?, pressure: pressure.data_byte()?
+                }),
+                _ => return Err(usage("poly:<note>:<pressure>"))
+            },
+            "at" | "aftertouch" => match *args {
+                [pressure] => Message::ChannelAftertouch(ChannelAftertouch {
+                    channel, pressure: pressure.data_byte()?
+                }),
+                _ => return Err(usage("at:<pressure>"))
+            },
+            "bend" => match *args {
+                [value] => Message::PitchBend(PitchBend { channel, value: value.value14()? }),
+                _       => return Err(usage("bend:<value>"))
+            },
+            // <name>:<value>, where an unknown name is most likely a misspelt controller
+            _ => match *args {
+                [value] => ControlChange::new(channel, self.controller(fields[0], DATA_MAX)?,
+                                              value.data_byte()?).into(),
+                _       => return Err(token.unexpected())
+            }
+        };
+
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::PITCH_BEND_CENTER;
+
+    // the messages of a program, ignoring channel overrides
+    fn messages(program: Program) -> Vec<Message> {
+        program.events.into_iter().map(|e| e.message).collect()
+    }
+
+    fn error_at(data: &str) -> (usize, ParseErrorKind) {
+        let e = Program::parse(data).unwrap_err();
+        (e.offset, e.kind)
+    }
+
+    #[test]
+    fn parses_separated_pairs() {
+        let program = Program::parse("70:104 74:124,122:0;1:1").unwrap();
+        assert_eq!(messages(program), vec![
+            ControlChange::new(0, 70, 104).into(),
+            ControlChange::new(0, 74, 124).into(),
+            ControlChange::new(0, 122, 0).into(),
+            ControlChange::new(0, 1, 1).into(),
+        ]);
+    }
+
+    #[test]
+    fn empty_input_is_empty_program() {
+        assert_eq!(Program::parse("").unwrap(), Program::default());
+        assert_eq!(Program::parse(" ,; ").unwrap(), Program::default());
+    }
+
+    #[test]
+    fn tokens_have_byte_offsets() {
+        let found: Vec<_> = tokens("  1:2,,33:44 ").map(|t| (t.offset, t.text)).collect();
+        assert_eq!(found, vec![(2, "1:2"), (7, "33:44")]);
+    }
+
+    #[test]
+    fn splits_on_multibyte_whitespace() {
+        let found: Vec<_> = tokens("1:1\u{a0}2:2\u{3000},3:3").map(|t| (t.offset, t.text)).collect();
+        assert_eq!(found, vec![(0, "1:1"), (5, "2:2"), (12, "3:3")]);
+    }
+
+    #[test]
+    fn comments_run_to_the_end_of_the_line() {
+        let found: Vec<_> = tokens("# init\npc:5 # patch\n  74:100#cutoff\n#").map(|t| (t.offset, t.text))
+            .collect();
+        assert_eq!(found, vec![(7, "pc:5"), (22, "74:100")]);
+        assert_eq!(Program::parse("# nothing but a comment").unwrap().events, vec![]);
+    }
+
+    #[test]
+    fn rejects_values_above_127() {
+        assert_eq!(error_at("1:2 74:128"),
+                   (7, ParseErrorKind::OutOfRange { value: "128".into(), max: 127 }));
+        assert_eq!(error_at("300:0"),
+                   (0, ParseErrorKind::OutOfRange { value: "300".into(), max: 127 }));
+        assert_eq!(error_at("1:99999999999999999999").0, 2);
+    }
+
+    #[test]
+    fn rejects_non_numbers() {
+        assert_eq!(error_at("1:x"), (2, ParseErrorKind::InvalidNumber("x".into())));
+        assert_eq!(error_at("1:-1"), (2, ParseErrorKind::InvalidNumber("-1".into())));
+        assert_eq!(error_at(":5"), (0, ParseErrorKind::InvalidNumber("".into())));
+    }
+
+    #[test]
+    fn rejects_leftover_garbage() {
+        assert_eq!(error_at("70:104 hello"), (7, ParseErrorKind::UnexpectedToken("hello".into())));
+        assert_eq!(error_at("1:2:3"), (0, ParseErrorKind::UnexpectedToken("1:2:3".into())));
+        assert_eq!(error_at("70:104/74:1"), (0, ParseErrorKind::UnexpectedToken("70:104/74:1".into())));
+    }
+
+    #[test]
+    fn parses_voice_messages() {
+        let program = Program::parse("cc:7:100 pc:5 note:60:100 off:60 noteoff:61:64 \
+                                      poly:60:20 at:30 bend:8192").unwrap();
+        assert_eq!(messages(program), vec![
+            ControlChange::new(0, 7, 100).into(),
+            Message::ProgramChange(ProgramChange { channel: 0, program: 5 }),
+            Message::NoteOn(NoteOn { channel: 0, note: 60, velocity: 100 }),
+            Message::NoteOff(NoteOff { channel: 0, note: 60, velocity: 0 }),
+            Message::NoteOff(NoteOff { channel: 0, note: 61, velocity: 64 }),
+            Message::PolyAftertouch(PolyAftertouch { channel: 0, note: 60, pressure: 20 }),
+            Message::ChannelAftertouch(ChannelAftertouch { channel: 0, pressure: 30 }),
+            Message::PitchBend(PitchBend { channel: 0, value: PITCH_BEND_CENTER }),
+        ]);
+    }
+
+    #[test]
+    fn checks_voice_message_fields() {
+        assert_eq!(error_at("1:1 pc:5:6"),
+                   (4, ParseErrorKind::Usage { token: "pc:5:6".into(), usage: "pc:<program>" }));
+        assert_eq!(error_at("bend:16384"),
+                   (5, ParseErrorKind::OutOfRange { value: "16384".into(), max: 16383 }));
+        assert_eq!(error_at("note:128:1"),
+                   (5, ParseErrorKind::OutOfRange { value: "128".into(), max: 127 }));
+        assert_eq!(error_at("wobble:1"), (0, ParseErrorKind::UnknownController("wobble".into())));
+        assert_eq!(error_at("wobble:1:2"), (0, ParseErrorKind::UnexpectedToken("wobble:1:2".into())));
+    }
+
+    #[test]
+    fn commas_inside_parameter_numbers_dont_split() {
+        let found: Vec<_> = tokens("rpn:0,0=2,1:2, nrpn:1,2=3").map(|t| t.text).collect();
+        assert_eq!(found, vec!["rpn:0,0=2", "1:2", "nrpn:1,2=3"]);
+    }
+
+    #[test]
+    fn expands_parameter_changes() {
+        let bytes = |program| messages(program).iter().map(Message::to_bytes).collect::<Vec<_>>();
+
+        assert_eq!(bytes(Program::parse("rpn:0,0=2").unwrap()),
+                   vec![vec![0xB0, 101, 0], vec![0xB0, 100, 0], vec![0xB0, 6, 2]]);
+
+        assert_eq!(bytes(Parser::new().rpn_null(true).parse("nrpn14:3,4=16383").unwrap()),
+                   vec![vec![0xB0, 99, 3], vec![0xB0, 98, 4], vec![0xB0, 6, 127], vec![0xB0, 38, 127],
+                        vec![0xB0, 101, 127], vec![0xB0, 100, 127]]);
+    }
+
+    #[test]
+    fn checks_parameter_changes() {
+        assert_eq!(error_at("rpn:0,0=128"),
+                   (8, ParseErrorKind::OutOfRange { value: "128".into(), max: 127 }));
+        assert_eq!(error_at("rpn14:0,128=1").0, 8);
+        assert_eq!(error_at("rpn:0=1"),
+                   (0, ParseErrorKind::Usage { token: "rpn:0=1".into(), usage: "rpn:<msb>,<lsb>=<value>" }));
+    }
+
+    #[test]
+    fn expands_14_bit_controllers() {
+        let program = Program::parse("cc14:1=12000").unwrap();
+        assert_eq!(messages(program), vec![
+            ControlChange::new(0, 1, 93).into(),
+            ControlChange::new(0, 33, 96).into(),
+        ]);
+
+        assert_eq!(error_at("cc14:32=0"),
+                   (5, ParseErrorKind::OutOfRange { value: "32".into(), max: 31 }));
+        assert_eq!(error_at("cc14:1=16384"),
+                   (7, ParseErrorKind::OutOfRange { value: "16384".into(), max: 16383 }));
+        assert_eq!(error_at("cc14:1:5"),
+                   (0, ParseErrorKind::Usage { token: "cc14:1:5".into(), usage: "cc14:<cc>=<value>" }));
+    }
+
+    #[test]
+    fn accepts_controller_names() {
+        assert_eq!(messages(Program::parse("volume:100 cc:Sustain:127 all-notes-off:0").unwrap()), vec![
+            ControlChange::new(0, 7, 100).into(),
+            ControlChange::new(0, 64, 127).into(),
+            ControlChange::new(0, 123, 0).into()
+        ]);
+        assert_eq!(messages(Program::parse("cc14:modwheel=8192").unwrap()), vec![
+            ControlChange::new(0, 1, 64).into(),
+            ControlChange::new(0, 33, 0).into()
+        ]);
+
+        let names  = ControllerNames::standard().alias("filter", 74).unwrap();
+        let parser = Parser::new().names(names);
+        assert_eq!(messages(parser.parse("filter:3").unwrap()), vec![ControlChange::new(0, 74, 3).into()]);
+    }
+
+    #[test]
+    fn rejects_unknown_controller_names() {
+        assert_eq!(error_at("voluem:100"), (0, ParseErrorKind::UnknownController("voluem".into())));
+        assert_eq!(error_at("1:1 ch2/voluem:100"), (8, ParseErrorKind::UnknownController("voluem".into())));
+        assert_eq!(error_at("voluem"), (0, ParseErrorKind::UnexpectedToken("voluem".into())));
+        assert_eq!(error_at("cc:voluem:100"), (3, ParseErrorKind::UnknownController("voluem".into())));
+        assert_eq!(error_at("cc14:sustain=1"),
+                   (5, ParseErrorKind::OutOfRange { value: "sustain".into(), max: 31 }));
+    }
+
+    #[test]
+    fn parses_ramps() {
+        let program = Parser::new().ramp_rate(2).parse("74:0..100@1s 1:1 cc:cutoff:100..0@500ms:exp").unwrap();
+        let events: Vec<_> = program.events.iter()
+            .map(|e| (e.at.as_millis(), e.message))
+            .collect();
+
+        assert_eq!(events, vec![
+            (0,    ControlChange::new(0, 74, 0).into()),
+            (500,  ControlChange::new(0, 74, 50).into()),
+            (1000, ControlChange::new(0, 74, 100).into()),
+            (1000, ControlChange::new(0, 1, 1).into()),
+            (1000, ControlChange::new(0, 74, 100).into()),
+            (1500, ControlChange::new(0, 74, 0).into())
+        ]);
+    }
+
+    #[test]
+    fn checks_ramps() {
+        let usage = |token: &str| ParseErrorKind::Usage {
+            token: token.into(), usage: "<cc>:<start>..<end>@<time>[:<curve>]"
+        };
+
+        assert_eq!(error_at("74:0..127"), (0, usage("74:0..127")));
+        assert_eq!(error_at("74:0..128@1s"),
+                   (6, ParseErrorKind::OutOfRange { value: "128".into(), max: 127 }));
+        assert_eq!(error_at("74:0..127@2"), (10, ParseErrorKind::InvalidDuration("2".into())));
+        assert_eq!(error_at("74:0..127@1s:wobbly"), (13, ParseErrorKind::UnknownCurve("wobbly".into())));
+    }
+
+    #[test]
+    fn ramps_must_end_in_time() {
+        assert_eq!(error_at("wait:4000000000s 74:0..127@300000000s"),
+                   (17, ParseErrorKind::DurationOutOfRange("74:0..127@300000000s".into())));
+        assert!(Program::parse("wait:4000000000s 74:0..127@200000000s").is_ok());
+    }
+
+    #[test]
+    fn durations_are_capped() {
+        let duration = |text| Token { offset: 0, text }.duration();
+        assert_eq!(duration("4294967295s"), Ok(MAX_TIME));
+        assert_eq!(duration("4294967296s").unwrap_err().kind,
+                   ParseErrorKind::DurationOutOfRange("4294967296s".into()));
+        assert!(duration("99999999999999999999999999999ms").is_err());
+    }
+
+    #[test]
+    fn waits_delay_later_messages() {
+        let program = Program::parse("pc:1 wait:50ms 1:1 wait:1s wait:0.5s 2:2").unwrap();
+        let times: Vec<_> = program.events.iter().map(|e| e.at.as_millis()).collect();
+        assert_eq!(times, vec![0, 50, 1550]);
+
+        assert_eq!(error_at("wait:5"), (5, ParseErrorKind::InvalidDuration("5".into())));
+        assert_eq!(error_at("wait:10000000000000000000s"),
+                   (5, ParseErrorKind::DurationOutOfRange("10000000000000000000s".into())));
+        assert_eq!(error_at("wait:4000000000s 1:1 wait:4000000000s 1:1"),
+                   (21, ParseErrorKind::DurationOutOfRange("wait:4000000000s".into())));
+        assert_eq!(error_at("wait"), (0, ParseErrorKind::Usage { token: "wait".into(), usage: "wait:<time>" }));
+        assert_eq!(error_at("ch2/wait:1s"), (4, ParseErrorKind::UnexpectedToken("wait:1s".into())));
+    }
+
+    #[test]
+    fn parses_durations() {
+        let duration = |text| Token { offset: 0, text }.duration();
+        assert_eq!(duration("2s"),    Ok(Duration::from_secs(2)));
+        assert_eq!(duration("0.25s"), Ok(Duration::from_millis(250)));
+        assert_eq!(duration("50ms"),  Ok(Duration::from_millis(50)));
+        assert!(duration("50").is_err());
+        assert!(duration("s").is_err());
+        assert!(duration("1e3s").is_err());
+    }
+
+    #[test]
+    fn channel_prefix_overrides_selection() {
+        let program = Program::parse("ch3/74:100 1:2 ch1-4/rpn:0,0=2").unwrap();
+        let channels: Vec<_> = program.events.iter().map(|e| e.channels).collect();
+
+        assert_eq!(channels, vec![
+            Some(Channels::only(2)),
+            None,
+            Some(Channels::parse("1-4").unwrap()),
+            Some(Channels::parse("1-4").unwrap()),
+            Some(Channels::parse("1-4").unwrap()),
+        ]);
+        assert_eq!(program.events[0].message, ControlChange::new(0, 74, 100).into());
+    }
+
+    #[test]
+    fn checks_channel_prefix() {
+        assert_eq!(error_at("ch17/1:1"),
+                   (2, ParseErrorKind::OutOfRange { value: "17".into(), max: 16 }));
+        assert_eq!(error_at("ch3/x"), (4, ParseErrorKind::UnexpectedToken("x".into())));
+        assert_eq!(error_at("ch3:1"), (0, ParseErrorKind::UnknownController("ch3".into())));
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt;
+
+use midir::{InitError, MidiInput, MidiOutput, PortInfoError};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{INPUT_PORT_NAME, OUTPUT_PORT_NAME};
+
+/// A single test applied to a port's number and name.
+#[derive(Debug, Clone)]
+pub enum PortFilter {
+    /// The name contains this string.
+    Contains(String),
+    /// The name is exactly this string.
+    Exact(String),
+    /// The name matches this regular expression.
+    Regex(Regex),
+    /// The port has this number, as shown by `cc-emitter list`.
+    Index(usize)
+}
+
+impl PortFilter {
+    /// Compile a regular expression filter.
+    pub fn regex(pattern: &str) -> Result<PortFilter, regex::Error> {
+        Regex::new(pattern).map(PortFilter::Regex)
+    }
+
+    pub fn matches(&self, index: usize, name: &str) -> bool {
+        match *self {
+            PortFilter::Contains(ref s) => name.contains(s.as_str()),
+            PortFilter::Exact(ref s)    => name == s,
+            PortFilter::Regex(ref re)   => re.is_match(name),
+            PortFilter::Index(i)        => index == i
+        }
+    }
+}
+
+impl fmt::Display for PortFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PortFilter::Contains(ref s) => write!(f, "name contains \"{}\"", s),
+            PortFilter::Exact(ref s)    => write!(f, "name is \"{}\"", s),
+            PortFilter::Regex(ref re)   => write!(f, "name matches /{}/", re),
+            PortFilter::Index(i)        => write!(f, "port is #{}", i)
+        }
+    }
+}
+
+/// Decides which output ports to connect to.
+///
+/// A port is selected if it matches every filter in `include` and none in `exclude`, so with no
+/// filters at all every port is selected.
+#[derive(Debug, Clone, Default)]
+pub struct PortSelector {
+    pub include: Vec<PortFilter>,
+    pub exclude: Vec<PortFilter>
+}
+
+impl PortSelector {
+    /// A selector matching every port.
+    pub fn all() -> PortSelector {
+        PortSelector::default()
+    }
+
+    /// A selector matching ports whose name contains `filter`.
+    pub fn name_contains<S: Into<String>>(filter: S) -> PortSelector {
+        PortSelector::all().include(PortFilter::Contains(filter.into()))
+    }
+
+    /// Additionally require ports to match `filter`.
+    pub fn include(mut self, filter: PortFilter) -> PortSelector {
+        self.include.push(filter);
+        self
+    }
+
+    /// Skip ports matching `filter`.
+    pub fn exclude(mut self, filter: PortFilter) -> PortSelector {
+        self.exclude.push(filter);
+        self
+    }
+
+    pub fn matches(&self, index: usize, name: &str) -> bool {
+        self.include.iter().all(|filter| filter.matches(index, name))
+            && !self.exclude.iter().any(|filter| filter.matches(index, name))
+    }
+}
+
+impl fmt::Display for PortSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return write!(f, "any port");
+        }
+
+        let conditions = self.include.iter().map(|filter| filter.to_string())
+            .chain(self.exclude.iter().map(|filter| format!("not {}", filter)))
+            .collect::<Vec<_>>();
+        write!(f, "{}", conditions.join(" and "))
+    }
+}
+
+/// Which way MIDI flows through a port, from our point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Input,
+    Output
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Direction::Input  => write!(f, "input"),
+            Direction::Output => write!(f, "output")
+        }
+    }
+}
+
+/// A description of one port, for listing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortInfo {
+    /// The port number, which is only stable until devices are added or removed.
+    pub index:     usize,
+    pub name:      String,
+    /// A backend identifier for the port, if it exposes one. For ALSA this is the
+    /// `client:port` address, which stays the same while the device stays plugged in.
+    pub id:        Option<String>,
+    pub direction: Direction
+}
+
+impl PortInfo {
+    pub fn new(index: usize, name: String, direction: Direction) -> PortInfo {
+        let id = alsa_address(&name).map(str::to_string);
+        PortInfo { index, name, id, direction }
+    }
+}
+
+/// Extract the ALSA `client:port` address which midir appends to port names.
+pub fn alsa_address(name: &str) -> Option<&str> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+
+    let address = name.rsplit(' ').next()?;
+    let mut numbers = address.split(':');
+    let is_number = |n: Option<&str>| n.is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+
+    if is_number(numbers.next()) && is_number(numbers.next()) && numbers.next().is_none() {
+        Some(address)
+    }
+    else {
+        None
+    }
+}
+
+/// Create a MIDI output client.
+pub fn make_output() -> Result<MidiOutput, InitError> {
+    MidiOutput::new(OUTPUT_PORT_NAME)
+}
+
+/// Create a MIDI input client.
+pub fn make_input() -> Result<MidiInput, InitError> {
+    MidiInput::new(INPUT_PORT_NAME)
+}
+
+/// Query the name of every output port, in port number order.
+pub fn list(output: &MidiOutput) -> Vec<(usize, Result<String, PortInfoError>)> {
+    (0..output.port_count())
+        .map(|port| (port, output.port_name(port)))
+        .collect()
+}
+
+/// Query the name of every input port, in port number order.
+pub fn list_inputs(input: &MidiInput) -> Vec<(usize, Result<String, PortInfoError>)> {
+    (0..input.port_count())
+        .map(|port| (port, input.port_name(port)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THROUGH: &str = "Midi Through:Midi Through Port-0 14:0";
+    const JUNO_1:  &str = "JUNO-DS:JUNO-DS MIDI 1 20:0";
+    const JUNO_2:  &str = "JUNO-DS:JUNO-DS MIDI 2 20:1";
+
+    #[test]
+    fn all_matches_everything() {
+        assert!(PortSelector::all().matches(0, THROUGH));
+        assert!(PortSelector::all().matches(1, ""));
+    }
+
+    #[test]
+    fn name_filter_is_substring_match() {
+        let selector = PortSelector::name_contains("JUNO");
+        assert!(selector.matches(1, JUNO_1));
+        assert!(!selector.matches(0, THROUGH));
+    }
+
+    #[test]
+    fn exact_regex_and_index_filters() {
+        assert!(PortFilter::Exact(JUNO_1.into()).matches(1, JUNO_1));
+        assert!(!PortFilter::Exact("JUNO-DS".into()).matches(1, JUNO_1));
+
+        let regex = PortFilter::regex(r"MIDI 1 \d+:\d+$").unwrap();
+        assert!(regex.matches(1, JUNO_1));
+        assert!(!regex.matches(2, JUNO_2));
+
+        assert!(PortFilter::Index(2).matches(2, JUNO_2));
+        assert!(!PortFilter::Index(2).matches(1, JUNO_1));
+    }
+
+    #[test]
+    fn filters_combine() {
+        let selector = PortSelector::name_contains("MIDI")
+            .exclude(PortFilter::Contains("Through".into()))
+            .exclude(PortFilter::Contains("MIDI 2".into()));
+
+        assert!(selector.matches(1, JUNO_1));
+        assert!(!selector.matches(2, JUNO_2));
+        assert!(!selector.matches(0, THROUGH));
+
+        assert_eq!(selector.to_string(),
+                   "name contains \"MIDI\" and not name contains \"Through\" and not name contains \"MIDI 2\"");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn extracts_alsa_address() {
+        assert_eq!(alsa_address(THROUGH), Some("14:0"));
+        assert_eq!(alsa_address(JUNO_2),  Some("20:1"));
+        assert_eq!(alsa_address("JUNO-DS"), None);
+        assert_eq!(alsa_address("Port 1:2:3"), None);
+        assert_eq!(alsa_address("Port :2"), None);
+
+        let info = PortInfo::new(2, JUNO_2.into(), Direction::Output);
+        assert_eq!(info.id.as_deref(), Some("20:1"));
+    }
+}
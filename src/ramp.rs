@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+/// Messages per second sent by a ramp, unless the `Parser` is told otherwise.
+pub const DEFAULT_RATE: u32 = 50;
+
+// most ticks a ramp is divided into, beyond which their times can't be told apart in an f64
+const MAX_INTERVALS: u64 = 1 << 40;
+
+// steepness of the exponential and logarithmic curves
+const CURVE_STEEPNESS: f64 = 4.0;
+
+/// The shape of a ramp between its start and end values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    /// Equal steps.
+    Linear,
+    /// Slow at first then faster, which sounds even for volume fades.
+    Exponential,
+    /// Fast at first then slower.
+    Logarithmic
+}
+
+impl Curve {
+    /// Look up a curve by name: `lin`, `exp` or `log` (or spelt out in full).
+    pub fn from_name(name: &str) -> Option<Curve> {
+        match name {
+            "lin" | "linear"      => Some(Curve::Linear),
+            "exp" | "exponential" => Some(Curve::Exponential),
+            "log" | "logarithmic" => Some(Curve::Logarithmic),
+            _                     => None
+        }
+    }
+
+    // map progress through the ramp, from 0 to 1, onto the fraction of the change applied
+    fn apply(self, x: f64) -> f64 {
+        let k = CURVE_STEEPNESS;
+        match self {
+            Curve::Linear      => x,
+            Curve::Exponential => ((k * x).exp() - 1.0) / (k.exp() - 1.0),
+            Curve::Logarithmic => (1.0 + (k.exp() - 1.0) * x).ln() / k
+        }
+    }
+
+    // the progress through the ramp at which a fraction of the change has been applied; the
+    // exponential and logarithmic curves are each other's inverse
+    fn invert(self, y: f64) -> f64 {
+        match self {
+            Curve::Linear      => y,
+            Curve::Exponential => Curve::Logarithmic.apply(y),
+            Curve::Logarithmic => Curve::Exponential.apply(y)
+        }
+    }
+}
+
+/// A controller sweeping from one value to another over a duration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ramp {
+    pub controller: u8,
+    pub start:      u8,
+    pub end:        u8,
+    pub duration:   Duration,
+    pub curve:      Curve
+}
+
+impl Ramp {
+    /// The values to send and when to send them, relative to the start of the ramp, at up to
+    /// `rate` messages per second.
+    ///
+    /// The first value is the start value, sent immediately, and the last is the end value, sent
+    /// no later than the end of the duration. Steps which wouldn't change the value are left out,
+    /// and a ramp with no duration just sends the end value.
+    pub fn steps(&self, rate: u32) -> Vec<(Duration, u8)> {
+        if self.duration == Duration::from_secs(0) {
+            return vec![(self.duration, self.end)];
+        }
+
+        let intervals = (self.duration.as_secs_f64() * f64::from(rate.max(1))).ceil().max(1.0) as u64;
+        let intervals = intervals.min(MAX_INTERVALS);
+        let change    = f64::from(self.end) - f64::from(self.start);
+        let value_at  = |i: u64| {
+            let x = i as f64 / intervals as f64;
+            (x, (f64::from(self.start) + change * self.curve.apply(x)).round() as u8)
+        };
+
+        // the value only changes at the ticks where it passes halfway to the next one, so look at
+        // those (and their neighbours, in case of rounding) rather than at every tick
+        let direction = change.signum();
+        let mut ticks = vec![0, intervals];
+        for v in 1..=(change.abs() as u8) {
+            let threshold = (f64::from(v) - 0.5) * direction / change;
+            let tick = (self.curve.invert(threshold) * intervals as f64).ceil() as u64;
+            ticks.extend([tick.saturating_sub(1), tick, tick + 1].iter().filter(|&&t| t <= intervals));
+        }
+        ticks.sort_unstable();
+        ticks.dedup();
+
+        let mut steps: Vec<(Duration, u8)> = Vec::new();
+        for (x, value) in ticks.into_iter().map(value_at) {
+            if steps.last().is_none_or(|&(_, last)| last != value) {
+                steps.push((self.duration.mul_f64(x), value));
+            }
+        }
+
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(start: u8, end: u8, millis: u64, curve: Curve) -> Ramp {
+        Ramp { controller: 74, start, end, duration: Duration::from_millis(millis), curve }
+    }
+
+    #[test]
+    fn linear_ramp_hits_both_ends_at_the_rate() {
+        let steps = ramp(0, 100, 1000, Curve::Linear).steps(10);
+        assert_eq!(steps.len(), 11);
+        assert_eq!(steps[0],  (Duration::from_millis(0), 0));
+        assert_eq!(steps[5],  (Duration::from_millis(500), 50));
+        assert_eq!(steps[10], (Duration::from_millis(1000), 100));
+    }
+
+    #[test]
+    fn ramps_can_go_down_and_skip_repeated_values() {
+        let steps = ramp(3, 0, 1000, Curve::Linear).steps(100);
+        let values: Vec<u8> = steps.iter().map(|&(_, value)| value).collect();
+        assert_eq!(values, vec![3, 2, 1, 0]);
+        assert!(steps.last().unwrap().0 <= Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn curves_bend_the_middle() {
+        let middle = |curve| ramp(0, 127, 1000, curve).steps(2)[1].1;
+        assert_eq!(middle(Curve::Linear), 64);
+        assert!(middle(Curve::Exponential) < 64);
+        assert!(middle(Curve::Logarithmic) > 64);
+    }
+
+    #[test]
+    fn steps_match_sampling_every_tick() {
+        for &curve in [Curve::Linear, Curve::Exponential, Curve::Logarithmic].iter() {
+            for &(start, end, millis, rate) in [(0, 127, 1000, 50), (127, 0, 2000, 1000),
+                                                (20, 30, 333, 7), (64, 0, 10, 10000)].iter() {
+                let ramp = ramp(start, end, millis, curve);
+                let intervals = (ramp.duration.as_secs_f64() * f64::from(rate)).ceil() as u32;
+
+                let mut expected: Vec<(Duration, u8)> = Vec::new();
+                for i in 0..=intervals {
+                    let x = f64::from(i) / f64::from(intervals);
+                    let value = (f64::from(start)
+                                 + (f64::from(end) - f64::from(start)) * curve.apply(x)).round() as u8;
+                    if expected.last().is_none_or(|&(_, last)| last != value) {
+                        expected.push((ramp.duration.mul_f64(x), value));
+                    }
+                }
+
+                assert_eq!(ramp.steps(rate), expected, "{:?} {}..{}", curve, start, end);
+            }
+        }
+    }
+
+    #[test]
+    fn long_ramps_take_no_longer_to_plan() {
+        let ramp  = Ramp { duration: Duration::from_secs(10_000_000), ..ramp(0, 127, 0, Curve::Exponential) };
+        let steps = ramp.steps(u32::MAX);
+        assert_eq!(steps.len(), 128);
+        assert_eq!(steps.last().unwrap().1, 127);
+    }
+
+    #[test]
+    fn instant_ramp_jumps_to_the_end() {
+        let steps = ramp(0, 127, 0, Curve::Linear).steps(DEFAULT_RATE);
+        assert_eq!(steps, vec![(Duration::from_millis(0), 127)]);
+    }
+}
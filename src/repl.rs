@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use crate::channels::Channels;
+use crate::config;
+use crate::error::Error;
+use crate::names::ControllerNames;
+use crate::parse::{Parser, Program, KEYWORDS};
+use crate::ports::PortFilter;
+
+/// Where the REPL's history is kept, relative to the data directory.
+pub const HISTORY_FILE: &str = "cc-emitter/history";
+
+/// Commands understood by the REPL besides data, with their help text.
+pub const COMMANDS: &[(&str, &str)] = &[
+    (".port",       "[text]     connect only to ports whose name contains text (all if omitted)"),
+    (".port-regex", "[pattern]  connect only to ports whose name matches a regular expression"),
+    (".exclude",    "[text]     also skip ports whose name contains text (none if omitted)"),
+    (".channel",    "[channels] send on these channels, e.g. 1-4,10 (all if omitted)"),
+    (".ports",      "           show the connected ports"),
+    (".help",       "           show this help"),
+    (".quit",       "           leave (or press Ctrl-D)")
+];
+
+/// One line of REPL input.
+#[derive(Debug)]
+pub enum Line {
+    /// Nothing but whitespace or a comment.
+    Empty,
+    /// Messages to send straight away.
+    Data(Program),
+    /// Replace the port selection with a single filter, or select every port.
+    Port(Option<PortFilter>),
+    /// Skip ports matching a filter as well, or stop skipping any.
+    Exclude(Option<PortFilter>),
+    /// Send on these channels from now on.
+    Channel(Channels),
+    /// Show the connected ports.
+    Ports,
+    Help,
+    Quit
+}
+
+impl Line {
+    /// Parse a line: a command starting with `.`, or data in the syntax `parser` accepts.
+    pub fn parse(line: &str, parser: &Parser) -> Result<Line, Error> {
+        let trimmed = line.trim();
+        if !trimmed.starts_with('.') {
+            let program = parser.parse(line)?;
+            return Ok(if program.events.is_empty() { Line::Empty } else { Line::Data(program) });
+        }
+
+        let (command, argument) = match trimmed.find(char::is_whitespace) {
+            Some(end) => (&trimmed[..end], trimmed[end..].trim()),
+            None      => (trimmed, "")
+        };
+        let filter = |filter| if argument.is_empty() { None } else { Some(filter) };
+
+        match command {
+            ".port"            => Ok(Line::Port(filter(PortFilter::Contains(argument.to_string())))),
+            ".port-regex"      => Ok(Line::Port(match argument {
+                ""      => None,
+                pattern => Some(PortFilter::regex(pattern)?)
+            })),
+            ".exclude"         => Ok(Line::Exclude(filter(PortFilter::Contains(argument.to_string())))),
+            ".channel"         => Ok(Line::Channel(match argument {
+                ""   => Channels::all(),
+                spec => Channels::parse(spec).map_err(|error| Error::Argument {
+                    name: "channels", value: spec.to_string(), error
+                })?
+            })),
+            ".ports"           => Ok(Line::Ports),
+            ".help"            => Ok(Line::Help),
+            ".quit" | ".exit"  => Ok(Line::Quit),
+            _                  => Err(Error::UnknownCommand(command.to_string()))
+        }
+    }
+}
+
+/// Completions for the word ending at byte `pos` of `line`: the byte offset the word starts at,
+/// and the commands, keywords or controller names it could be.
+pub fn complete(line: &str, pos: usize, names: &ControllerNames) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start  = before.rfind(|c: char| c.is_whitespace() || ",;:/=.".contains(c))
+        .map_or(0, |n| n + 1);
+    let word   = before[start..].to_lowercase();
+
+    // commands only come first on the line
+    if before.trim_start().starts_with('.') && !before.trim_start().contains(char::is_whitespace) {
+        let start = before.len() - before.trim_start().len();
+        return (start, COMMANDS.iter()
+            .map(|&(command, _)| command.to_string())
+            .filter(|command| command.starts_with(before.trim_start()))
+            .collect());
+    }
+
+    // keywords can start a token, while names can also follow one, as in cc:volume:100
+    let starts_token = before[..start].chars().next_back()
+        .is_none_or(|c| c.is_whitespace() || ",;/".contains(c));
+    let keywords = KEYWORDS.iter().cloned().filter(|_| starts_token);
+
+    let mut candidates: Vec<String> = keywords.chain(names.names())
+        .filter(|candidate| candidate.to_lowercase().starts_with(&word))
+        .map(str::to_string)
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+
+    (start, candidates)
+}
+
+/// The REPL history file: `$XDG_DATA_HOME/cc-emitter/history`, falling back to
+/// `~/.local/share/cc-emitter/history`.
+pub fn history_path() -> Option<PathBuf> {
+    Some(config::xdg_dir("XDG_DATA_HOME", Some(".local/share"))?.join(HISTORY_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Line {
+        Line::parse(line, &Parser::new()).unwrap()
+    }
+
+    #[test]
+    fn parses_data_and_commands() {
+        assert!(matches!(parse("74:100 pc:5"), Line::Data(ref program) if program.events.len() == 2));
+        assert!(matches!(parse("   # just a comment"), Line::Empty));
+        assert!(matches!(parse(".port JUNO DS"), Line::Port(Some(PortFilter::Contains(ref s))) if s == "JUNO DS"));
+        assert!(matches!(parse(".port"), Line::Port(None)));
+        assert!(matches!(parse(".exclude MIDI 2"), Line::Exclude(Some(_))));
+        assert!(matches!(parse(" .channel 1-4"), Line::Channel(channels) if channels.len() == 4));
+        assert!(matches!(parse(".channel"), Line::Channel(channels) if channels.len() == 16));
+        assert!(matches!(parse(".quit"), Line::Quit));
+
+        assert!(matches!(Line::parse(".frobnicate", &Parser::new()), Err(Error::UnknownCommand(_))));
+        assert!(matches!(Line::parse(".port-regex (", &Parser::new()), Err(Error::Regex(_))));
+        assert!(matches!(Line::parse("74:200", &Parser::new()), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn completes_names_keywords_and_commands() {
+        let names = ControllerNames::standard().alias("filter", 74).unwrap();
+
+        assert_eq!(complete("74:1 vol", 8, &names), (5, vec!["volume".to_string(), "volumelsb".to_string()]));
+        assert_eq!(complete("fil", 3, &names),      (0, vec!["filter".to_string()]));
+        assert_eq!(complete("no", 2, &names),       (0, vec!["note".to_string(), "noteoff".to_string()]));
+        assert_eq!(complete("cc:no", 5, &names),    (3, vec![]));
+        assert_eq!(complete("ch3/sus", 7, &names),  (4, vec!["sustain".to_string()]));
+        assert_eq!(complete(".po", 3, &names),      (0, vec![".port".to_string(), ".port-regex".to_string(),
+                                                             ".ports".to_string()]));
+    }
+}
@@ -0,0 +1,331 @@
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+
+use crate::channels::Channels;
+use crate::config;
+use crate::message::{ChannelAftertouch, ControlChange, Message, NoteOff, NoteOn, PolyAftertouch,
+                     ProgramChange};
+use crate::monitor::Kind;
+use crate::names::ControllerNames;
+
+/// A route from one device's input ports to output ports, with rules to transform the messages
+/// passing along it.
+///
+/// ```toml
+/// [route.keys]
+/// from = "keystep"
+/// to = "juno"
+///
+/// [[route.keys.rule]]
+/// cc = "modwheel"
+/// to-cc = "filter"
+/// to-range = [20, 100]
+///
+/// [[route.keys.rule]]
+/// type = "at"
+/// drop = true
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// The device whose input ports are listened to.
+    pub from:  String,
+    /// The device whose output ports are sent to, unless the command line names one.
+    #[serde(default)]
+    pub to:    Option<String>,
+    /// Rules tried in order on each message. The first which matches decides what's sent, and
+    /// messages no rule matches are sent unchanged.
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>
+}
+
+/// Which messages a rule applies to and what it does to them.
+///
+/// Every condition given must hold for the rule to match. Rescaling and inverting apply to the
+/// 7-bit value of the message: a controller's value, a velocity, a pressure or a program number.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Rule {
+    /// Match only this kind of message: note, poly, cc, pc, at, bend, sysex or system.
+    #[serde(rename = "type", deserialize_with = "deserialize_kind")]
+    pub kind:       Option<Kind>,
+    /// Match only channel messages on these channels.
+    #[serde(deserialize_with = "config::deserialize_channels")]
+    pub channel:    Option<Channels>,
+    /// Match only Control Changes to this controller.
+    pub cc:         Option<Controller>,
+    /// Send Control Changes to this controller instead.
+    pub to_cc:      Option<Controller>,
+    /// Send on this channel (1-16) instead.
+    pub to_channel: Option<u8>,
+    /// The values coming in, as `[low, high]`; values outside are clamped. Defaults to `[0, 127]`.
+    pub range:      Option<(u8, u8)>,
+    /// The values to send, which `range` is scaled to. Defaults to `[0, 127]`.
+    pub to_range:   Option<(u8, u8)>,
+    /// Turn the values upside down, so the low end of `range` goes to the high end of `to-range`.
+    pub invert:     bool,
+    /// Send nothing at all for matching messages.
+    pub drop:       bool
+}
+
+/// A controller in a rule, by number or by name.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Controller {
+    Number(u8),
+    Name(String)
+}
+
+impl Controller {
+    fn resolve(&self, names: &ControllerNames) -> Result<u8, String> {
+        match *self {
+            Controller::Number(n) if n <= 127 => Ok(n),
+            Controller::Number(n)             => Err(format!("controller {} is out of range 0-127", n)),
+            Controller::Name(ref name)        => names.lookup(name)
+                .ok_or_else(|| format!("unknown controller \"{}\"", name))
+        }
+    }
+}
+
+/// A route's rules, checked and with their controller names looked up, ready to apply.
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    rules: Vec<Transform>
+}
+
+// a rule with its defaults filled in
+#[derive(Debug, Clone)]
+struct Transform {
+    kind:       Option<Kind>,
+    channels:   Option<Channels>,
+    cc:         Option<u8>,
+    to_cc:      Option<u8>,
+    to_channel: Option<u8>,
+    range:      (u8, u8),
+    to_range:   (u8, u8),
+    invert:     bool,
+    drop:       bool
+}
+
+impl Route {
+    /// Check the rules and look up their controllers: those matched in `from_names`, and those
+    /// sent to in `to_names`.
+    ///
+    /// Returns a description of the first bad rule, numbered from 1.
+    pub fn router(&self, from_names: &ControllerNames, to_names: &ControllerNames)
+        -> Result<Router, String>
+    {
+        let rules = self.rules.iter().enumerate()
+            .map(|(i, rule)| rule.compile(from_names, to_names)
+                 .map_err(|reason| format!("rule {}: {}", i + 1, reason)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Router { rules })
+    }
+}
+
+impl Rule {
+    fn compile(&self, from_names: &ControllerNames, to_names: &ControllerNames)
+        -> Result<Transform, String>
+    {
+        let range = self.range.unwrap_or((0, 127));
+        let to_range = self.to_range.unwrap_or((0, 127));
+
+        if range.0 > 127 || range.1 > 127 || to_range.0 > 127 || to_range.1 > 127 {
+            return Err("ranges must lie within 0-127".to_string());
+        }
+        if range.0 == range.1 {
+            return Err("range must span more than one value".to_string());
+        }
+        if let Some(channel) = self.to_channel.filter(|channel| !(1..=16).contains(channel)) {
+            return Err(format!("to-channel {} is out of range 1-16", channel));
+        }
+
+        Ok(Transform {
+            kind:       self.kind,
+            channels:   self.channel,
+            cc:         self.cc.as_ref().map(|cc| cc.resolve(from_names)).transpose()?,
+            to_cc:      self.to_cc.as_ref().map(|cc| cc.resolve(to_names)).transpose()?,
+            to_channel: self.to_channel.map(|channel| channel - 1),
+            range,
+            to_range,
+            invert:     self.invert,
+            drop:       self.drop
+        })
+    }
+}
+
+impl Router {
+    /// What to send for a received message: the message transformed by the first rule which
+    /// matches it, the message unchanged if none does, or nothing if it's dropped.
+    pub fn apply(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+        match self.rules.iter().find(|rule| rule.matches(bytes)) {
+            Some(rule) if rule.drop => None,
+            Some(rule)              => Some(rule.transform(bytes)),
+            None                    => Some(bytes.to_vec())
+        }
+    }
+}
+
+impl Transform {
+    fn matches(&self, bytes: &[u8]) -> bool {
+        let message = Message::from_bytes(bytes);
+
+        self.kind.is_none_or(|kind| kind == Kind::of(bytes))
+            && self.channels.is_none_or(|channels| {
+                message.is_some_and(|message| channels.contains(message.channel()))
+            })
+            && self.cc.is_none_or(|cc| {
+                matches!(message, Some(Message::ControlChange(m)) if m.controller == cc)
+            })
+    }
+
+    fn transform(&self, bytes: &[u8]) -> Vec<u8> {
+        // only channel messages can be changed
+        let message = match Message::from_bytes(bytes) {
+            Some(message) => message,
+            None          => return bytes.to_vec()
+        };
+
+        let message = match message {
+            Message::ControlChange(m) => Message::ControlChange(ControlChange {
+                controller: self.to_cc.unwrap_or(m.controller),
+                value:      self.scale(m.value),
+                ..m
+            }),
+            // a Note On with no velocity is a Note Off, which mustn't become a Note On
+            Message::NoteOn(m) if m.velocity > 0 =>
+                Message::NoteOn(NoteOn { velocity: self.scale(m.velocity).max(1), ..m }),
+            Message::NoteOff(m) =>
+                Message::NoteOff(NoteOff { velocity: self.scale(m.velocity), ..m }),
+            Message::PolyAftertouch(m) =>
+                Message::PolyAftertouch(PolyAftertouch { pressure: self.scale(m.pressure), ..m }),
+            Message::ProgramChange(m) =>
+                Message::ProgramChange(ProgramChange { program: self.scale(m.program), ..m }),
+            Message::ChannelAftertouch(m) =>
+                Message::ChannelAftertouch(ChannelAftertouch { pressure: self.scale(m.pressure), ..m }),
+            message => message
+        };
+
+        match self.to_channel {
+            Some(channel) => message.on_channel(channel).to_bytes(),
+            None          => message.to_bytes()
+        }
+    }
+
+    // map a value from the incoming range to the outgoing one
+    fn scale(&self, value: u8) -> u8 {
+        let (low, high)       = self.range;
+        let (to_low, to_high) = self.to_range;
+
+        let value    = value.clamp(low.min(high), low.max(high));
+        let position = (value as f64 - low as f64) / (high as f64 - low as f64);
+        let position = if self.invert { 1.0 - position } else { position };
+
+        (to_low as f64 + position * (to_high as f64 - to_low as f64)).round() as u8
+    }
+}
+
+// message kinds are written the same way as for `monitor --type`
+fn deserialize_kind<'de, D>(deserializer: D) -> Result<Option<Kind>, D::Error>
+    where D: Deserializer<'de>
+{
+    let name = String::deserialize(deserializer)?;
+    Kind::from_name(&name)
+        .map(Some)
+        .ok_or_else(|| de::Error::custom(format_args!("unknown message type \"{}\"", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn router(rules: &str) -> Router {
+        let config = Config::parse(&format!("[route.test]\nfrom = \"keys\"\n{}", rules)).unwrap();
+        let names  = ControllerNames::standard().alias("filter", 74).unwrap();
+        config.route("test").unwrap().router(&names, &names).unwrap()
+    }
+
+    #[test]
+    fn remaps_controllers_and_channels() {
+        let router = router(r#"
+            [[route.test.rule]]
+            cc = "modwheel"
+            to-cc = "filter"
+
+            [[route.test.rule]]
+            channel = 1
+            to-channel = 10
+        "#);
+
+        assert_eq!(router.apply(&[0xB1, 1, 64]),   Some(vec![0xB1, 74, 64]));
+        assert_eq!(router.apply(&[0x90, 60, 100]), Some(vec![0x99, 60, 100]));
+        assert_eq!(router.apply(&[0xB0, 1, 64]),   Some(vec![0xB0, 74, 64]));
+        assert_eq!(router.apply(&[0x91, 60, 100]), Some(vec![0x91, 60, 100]));
+    }
+
+    #[test]
+    fn rescales_and_inverts_values() {
+        let router = router(r#"
+            [[route.test.rule]]
+            cc = 7
+            to-range = [20, 100]
+
+            [[route.test.rule]]
+            cc = 11
+            range = [0, 63]
+            invert = true
+
+            [[route.test.rule]]
+            type = "note"
+            to-range = [64, 127]
+        "#);
+
+        assert_eq!(router.apply(&[0xB0, 7, 0]),    Some(vec![0xB0, 7, 20]));
+        assert_eq!(router.apply(&[0xB0, 7, 127]),  Some(vec![0xB0, 7, 100]));
+        assert_eq!(router.apply(&[0xB0, 11, 0]),   Some(vec![0xB0, 11, 127]));
+        assert_eq!(router.apply(&[0xB0, 11, 100]), Some(vec![0xB0, 11, 0]));
+        assert_eq!(router.apply(&[0x90, 60, 1]),   Some(vec![0x90, 60, 64]));
+        assert_eq!(router.apply(&[0x90, 60, 0]),   Some(vec![0x90, 60, 0]));
+    }
+
+    #[test]
+    fn drops_matching_messages() {
+        let router = router(r#"
+            [[route.test.rule]]
+            type = "system"
+            drop = true
+
+            [[route.test.rule]]
+            type = "at"
+            channel = "2-3"
+            drop = true
+        "#);
+
+        assert_eq!(router.apply(&[0xF8]),        None);
+        assert_eq!(router.apply(&[0xD1, 40]),    None);
+        assert_eq!(router.apply(&[0xD0, 40]),    Some(vec![0xD0, 40]));
+        assert_eq!(router.apply(&[0xF0, 0xF7]),  Some(vec![0xF0, 0xF7]));
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        let names = ControllerNames::standard();
+        let check = |rules: &str| {
+            let config = Config::parse(&format!("[route.test]\nfrom = \"keys\"\n{}", rules)).unwrap();
+            config.route("test").unwrap().router(&names, &names)
+        };
+
+        assert!(check("[[route.test.rule]]\ncc = 1").is_ok());
+        assert_eq!(check("[[route.test.rule]]\ncc = 1\n[[route.test.rule]]\ncc = \"cutof\"").unwrap_err(),
+                   "rule 2: unknown controller \"cutof\"");
+        assert!(check("[[route.test.rule]]\nto-cc = 128").is_err());
+        assert!(check("[[route.test.rule]]\nto-channel = 0").is_err());
+        assert!(check("[[route.test.rule]]\nrange = [64, 64]").is_err());
+        assert!(check("[[route.test.rule]]\nto-range = [0, 200]").is_err());
+
+        assert!(Config::parse("[route.test]\nfrom = \"keys\"\n[[route.test.rule]]\ntype = \"clock\"").is_err());
+        assert!(Config::parse("[route.test]\nfrom = \"keys\"\n[[route.test.rule]]\ncc = 1\nscale = 2").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::parse::{tokens, ParseError, ParseErrorKind};
+
+// MIDI protocol constants
+pub const SYSEX_START: u8 = 0xF0;
+pub const SYSEX_END: u8   = 0xF7;
+
+/// A complete System Exclusive message, including the F0 and F7 framing bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysEx {
+    bytes: Vec<u8>
+}
+
+impl SysEx {
+    /// Validate a single framed message.
+    pub fn new(bytes: Vec<u8>) -> Result<SysEx, SysExError> {
+        let mut messages = split(&bytes)?;
+        match messages.len() {
+            1 => Ok(messages.remove(0)),
+            0 => Err(SysExError::new(0, SysExErrorKind::Empty)),
+            _ => Err(SysExError::new(messages[0].len(), SysExErrorKind::MultipleMessages))
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Length in bytes, including framing.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl fmt::Display for SysEx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.bytes.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// What was wrong with the SysEx framing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysExErrorKind {
+    /// There were no messages at all.
+    Empty,
+    /// A byte outside a message wasn't F0.
+    ExpectedStart(u8),
+    /// A byte inside a message wasn't a 7-bit data byte or F7.
+    InvalidDataByte(u8),
+    /// The message starting here had no closing F7.
+    Unterminated,
+    /// More than one message was given where only one was expected.
+    MultipleMessages
+}
+
+/// A SysEx framing error, located by byte index into the raw message data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysExError {
+    pub offset: usize,
+    pub kind:   SysExErrorKind
+}
+
+impl SysExError {
+    pub fn new(offset: usize, kind: SysExErrorKind) -> SysExError {
+        SysExError { offset, kind }
+    }
+}
+
+impl fmt::Display for SysExError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            SysExErrorKind::Empty =>
+                write!(f, "no SysEx messages found"),
+            SysExErrorKind::ExpectedStart(b) =>
+                write!(f, "expected F0 at byte {}, found {:02X}", self.offset, b),
+            SysExErrorKind::InvalidDataByte(b) =>
+                write!(f, "{:02X} at byte {} is not a 7-bit data byte", b, self.offset),
+            SysExErrorKind::Unterminated =>
+                write!(f, "message starting at byte {} has no closing F7", self.offset),
+            SysExErrorKind::MultipleMessages =>
+                write!(f, "expected a single message, but another starts at byte {}", self.offset)
+        }
+    }
+}
+
+impl error::Error for SysExError {}
+
+/// Split raw bytes into framed SysEx messages, validating each one.
+pub fn split(bytes: &[u8]) -> Result<Vec<SysEx>, SysExError> {
+    let mut messages = Vec::new();
+    let mut start    = 0;
+
+    while start < bytes.len() {
+        if bytes[start] != SYSEX_START {
+            return Err(SysExError::new(start, SysExErrorKind::ExpectedStart(bytes[start])));
+        }
+
+        // find the closing F7, checking every data byte along the way
+        let mut end = start + 1;
+        loop {
+            match bytes.get(end) {
+                Some(&SYSEX_END)          => break,
+                Some(&b) if b & 0x80 == 0 => end += 1,
+                Some(&b) => return Err(SysExError::new(end, SysExErrorKind::InvalidDataByte(b))),
+                None     => return Err(SysExError::new(start, SysExErrorKind::Unterminated))
+            }
+        }
+
+        messages.push(SysEx { bytes: bytes[start..=end].to_vec() });
+        start = end + 1;
+    }
+
+    Ok(messages)
+}
+
+/// Decode hex text such as `F0 41 10 42 F7` into raw bytes.
+///
+/// Bytes may be separated by whitespace, commas or semicolons, or run together (`F04110`).
+pub fn decode_hex(text: &str) -> Result<Vec<u8>, ParseError> {
+    let mut bytes = Vec::new();
+
+    for token in tokens(text) {
+        let digits = token.text.as_bytes();
+
+        if digits.len() % 2 != 0 || !digits.iter().all(u8::is_ascii_hexdigit) {
+            return Err(token.error(ParseErrorKind::InvalidNumber(token.text.to_string())));
+        }
+
+        // every character is an ASCII hex digit, so these slices are valid UTF-8 and parse
+        for pair in token.text.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).unwrap();
+            bytes.push(u8::from_str_radix(pair, 16).unwrap());
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Parse hex text holding one or more messages.
+pub fn parse_hex(text: &str) -> Result<Vec<SysEx>, Error> {
+    Ok(split(&decode_hex(text)?)?)
+}
+
+/// Read every message from a `.syx` file, which holds the raw bytes back-to-back.
+pub fn read_syx<P: AsRef<Path>>(path: P) -> Result<Vec<SysEx>, Error> {
+    let path  = path.as_ref();
+    let bytes = fs::read(path).map_err(|error| Error::Io { path: path.to_path_buf(), error })?;
+    Ok(split(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_spaced_and_packed_hex() {
+        assert_eq!(decode_hex("F0 41 10 f7").unwrap(), vec![0xF0, 0x41, 0x10, 0xF7]);
+        assert_eq!(decode_hex("F04110F7").unwrap(),    vec![0xF0, 0x41, 0x10, 0xF7]);
+        assert_eq!(decode_hex("F0\u{a0}F7").unwrap(),  vec![0xF0, 0xF7]);
+    }
+
+    #[test]
+    fn rejects_bad_hex() {
+        assert_eq!(decode_hex("F0 4 F7").unwrap_err().offset, 3);
+        assert_eq!(decode_hex("F0 GG F7").unwrap_err().offset, 3);
+    }
+
+    #[test]
+    fn splits_back_to_back_messages() {
+        let messages = split(&[0xF0, 0x7E, 0xF7, 0xF0, 0x41, 0x10, 0xF7]).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].as_bytes(), &[0xF0, 0x7E, 0xF7]);
+        assert_eq!(messages[1].to_string(), "F0 41 10 F7");
+    }
+
+    #[test]
+    fn validates_framing() {
+        let kind = |bytes: &[u8]| split(bytes).map_err(|e| (e.offset, e.kind));
+
+        assert_eq!(kind(&[0x41, 0xF7]),             Err((0, SysExErrorKind::ExpectedStart(0x41))));
+        assert_eq!(kind(&[0xF0, 0x41, 0x80, 0xF7]), Err((2, SysExErrorKind::InvalidDataByte(0x80))));
+        assert_eq!(kind(&[0xF0, 0xF7, 0xF0, 0x41]), Err((2, SysExErrorKind::Unterminated)));
+    }
+
+    #[test]
+    fn new_requires_exactly_one_message() {
+        assert!(SysEx::new(vec![0xF0, 0x01, 0xF7]).is_ok());
+        assert_eq!(SysEx::new(vec![]).unwrap_err().kind, SysExErrorKind::Empty);
+        assert_eq!(SysEx::new(vec![0xF0, 0xF7, 0xF0, 0xF7]).unwrap_err(),
+                   SysExError::new(2, SysExErrorKind::MultipleMessages));
+    }
+}